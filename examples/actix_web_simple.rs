@@ -15,13 +15,17 @@ async fn auth(login_info: wx_login::WxLoginInfo) -> impl Responder {
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     tracing_subscriber::fmt::init();
-    HttpServer::new(|| {
+    // create the middleware of wx_login_middleware
+    // here we use config of app-info from environment variables
+    // (e.g. WX_APP_"TheAppID"="TheAppSecret")
+    let wx_login = wx_login::actix_web::middleware_with_env_var();
+    HttpServer::new(move || {
         App::new()
-            // add the middleware for login and authentication
+            // add the middleware for authentication
+            .wrap(wx_login.clone())
+            // mount the login and refresh handlers
             // by default the login API is `GET|POST /login`
-            // here we use config of app-info from environment variables
-            // (e.g. WX_APP_"TheAppID"="TheAppSecret")
-            .wrap(wx_login::actix_web::middleware_with_env_var())
+            .service(wx_login.login_services())
             .service(hello)
             // `GET /auth` require login authendication
             .service(auth)
@@ -29,4 +33,4 @@ async fn main() -> std::io::Result<()> {
     .bind(("127.0.0.1", 8080))?
     .run()
     .await
-}
\ No newline at end of file
+}
@@ -6,17 +6,22 @@ async fn main() {
     // initialize tracing
     tracing_subscriber::fmt::init();
 
+    // create the layer of wx_login_middleware
+    // here we use default config of app-info from environment variables
+    // (e.g. WX_APP_"TheAppID"="TheAppSecret")
+    let wx_login = wx_login::axum::layer_with_env_var();
+
     // build our application with a route
     let app = Router::new()
         // `GET /` goes to `root`
         .route("/", get(root))
         // `GET /auth` goes to `auth` which require login authendication
         .route("/auth", get(auth))
-        // add the layer of wx_login_middleware for login and authentication
+        // add the layer for authentication
+        .layer(wx_login.clone())
+        // mount the login and refresh handlers after the layer, which only wraps the routes added before
         // by default the login API is `GET|POST /login`
-        // here we use default config of app-info from environment variables
-        // (e.g. WX_APP_"TheAppID"="TheAppSecret")
-        .layer(wx_login::axum::layer_with_env_var());
+        .merge(wx_login.login_router());
 
     // run our app with hyper, listening globally on port 3000
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
@@ -73,6 +73,21 @@ export const api = {
             throw err;
         }
     },
+    // refresh stoken and skey without calling wx.login
+    async refresh() {
+        try {
+            const res = await this.request({
+                url: '/login/refresh',
+                method: "POST"
+            });
+            console.log('api refresh ok: ', res);
+            this.loginState = res.data;
+            return res;
+        } catch (err) {
+            console.error('api refresh err: ', err);
+            throw err;
+        }
+    },
     // request
     async request(params) {
         if (params.url.startsWith('/')) {
@@ -1,38 +1,53 @@
 use std::{
     fmt::Display,
     future::{ready, Ready},
+    pin::Pin,
     rc::Rc,
     sync::Arc,
 };
 
 use actix_web::{
     body::{BoxBody, EitherBody},
-    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    http, web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, Responder,
-    ResponseError,
+    dev::{
+        forward_ready, HttpServiceFactory, Payload, Service, ServiceRequest, ServiceResponse,
+        Transform,
+    },
+    error::PayloadError,
+    http::{self, header::HeaderMap},
+    web::{self, Bytes, BytesMut},
+    Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, Resource, Responder, ResponseError,
+    Scope,
 };
-use futures_util::future::LocalBoxFuture;
+use futures_util::{future::LocalBoxFuture, stream, Stream, StreamExt};
 use serde::Deserialize;
 
 use crate::core::{
     config::{Config, ConfigBuilder},
     login::{
-        self, Error as LoginError, WxLoginErr, WxLoginInfo, WxLoginOk, AUTH_FAIL_MSG,
-        LOGIN_FAIL_MSG,
+        auth_err_resp, extract_login_info, extract_optional_login_info, extract_signed_login_info,
+        Error as LoginError, OptionalWxLoginInfo, SignedWxLoginInfo, WxLogin, WxLoginAuthResult,
+        WxLoginErr, WxLoginInfo, WxLoginOk, WxLoginRejection, LOGIN_FAIL_MSG,
     },
+    reload::ConfigHandle,
+    render::render_error,
+    security::SigRequest,
+    session::ClientInfo,
 };
 
-type WxLoginAuthResult = Result<WxLoginInfo, LoginError>;
+/// The names of the login, refresh and logout resources, by which the middleware passes them through
+/// wherever they are mounted.
+const LOGIN_RESOURCE_NAMES: [&str; 3] = ["wx_login.login", "wx_login.refresh", "wx_login.logout"];
 
 /// Create a [WxLoginMiddleware] with config derived from environment variables and default values.
 pub fn middleware_with_env_var() -> WxLoginMiddleware {
     WxLoginMiddleware::new_with_env_var()
 }
 
-/// A actix-web middleware wrapping the functionality of this crate.
+/// A actix-web middleware authenticating the requests,
+/// whose login, refresh and logout handlers are mounted by [login_scope](WxLoginMiddleware::login_scope) etc.
 #[derive(Clone)]
 pub struct WxLoginMiddleware {
-    cfg: Arc<Config>,
+    cfg: ConfigHandle,
 }
 
 impl WxLoginMiddleware {
@@ -41,7 +56,65 @@ impl WxLoginMiddleware {
     }
 
     pub fn new(cfg: Config) -> Self {
-        Self { cfg: Arc::new(cfg) }
+        Self { cfg: cfg.into() }
+    }
+
+    /// Create a [WxLoginMiddleware] with a reloadable [ConfigHandle].
+    pub fn with_config_handle(cfg: ConfigHandle) -> Self {
+        Self { cfg }
+    }
+
+    /// Get the login, refresh and logout resources at the paths of config, to be mounted by `App::service`.
+    ///
+    /// Note that the paths are fixed when the resources are created, even if the config is reloaded.
+    pub fn login_services(&self) -> impl HttpServiceFactory {
+        (
+            self.login_resource(),
+            self.refresh_resource(),
+            self.logout_resource(),
+        )
+    }
+
+    /// Get a scope of the login, refresh and logout resources at the paths of config under the prefix.
+    pub fn login_scope(&self, prefix: &str) -> Scope {
+        web::scope(prefix)
+            .service(self.login_resource())
+            .service(self.refresh_resource())
+            .service(self.logout_resource())
+    }
+
+    /// Get the login resource (`GET|POST`) at the login path of config.
+    ///
+    /// The resources of login, refresh and logout are named (e.g. `wx_login.login`) to be passed through
+    /// by the middleware, so they should not be renamed.
+    pub fn login_resource(&self) -> Resource {
+        let wx_login = WxLogin::new(self.cfg.clone());
+        let handler =
+            move |req: HttpRequest, payload: web::Payload| login(wx_login.clone(), req, payload);
+        web::resource(&self.cfg.load().login_path)
+            .name(LOGIN_RESOURCE_NAMES[0])
+            .route(web::get().to(handler.clone()))
+            .route(web::post().to(handler))
+    }
+
+    /// Get the refresh resource (`POST`) at the refresh path of config.
+    pub fn refresh_resource(&self) -> Resource {
+        let wx_login = WxLogin::new(self.cfg.clone());
+        let handler =
+            move |req: HttpRequest, payload: web::Payload| refresh(wx_login.clone(), req, payload);
+        web::resource(&self.cfg.load().refresh_path)
+            .name(LOGIN_RESOURCE_NAMES[1])
+            .route(web::post().to(handler))
+    }
+
+    /// Get the logout resource (`POST`) at the logout path of config.
+    pub fn logout_resource(&self) -> Resource {
+        let wx_login = WxLogin::new(self.cfg.clone());
+        let handler =
+            move |req: HttpRequest, payload: web::Payload| logout(wx_login.clone(), req, payload);
+        web::resource(&self.cfg.load().logout_path)
+            .name(LOGIN_RESOURCE_NAMES[2])
+            .route(web::post().to(handler))
     }
 }
 
@@ -63,7 +136,7 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(WxLoginMiddlewareService {
             service: Rc::new(service),
-            wx_login: login::WxLogin::new(self.cfg.clone()),
+            wx_login: WxLogin::new(self.cfg.clone()),
         }))
     }
 }
@@ -71,7 +144,7 @@ where
 /// A actix-web middleware service created by [WxLoginMiddleware].
 pub struct WxLoginMiddlewareService<S> {
     service: Rc<S>,
-    wx_login: login::WxLogin,
+    wx_login: WxLogin,
 }
 
 impl<S> Clone for WxLoginMiddlewareService<S> {
@@ -96,83 +169,222 @@ where
     forward_ready!(service);
 
     fn call(&self, mut req: ServiceRequest) -> Self::Future {
-        #[derive(Deserialize)]
-        struct LoginRequest {
-            appid: String,
-            code: String,
-        }
-
         let myself = (*self).clone();
 
         Box::pin(async move {
-            // if req.uri().path() == "/login" {
-            if req.uri().path() == myself.wx_login.cfg.login_path {
-                let LoginRequest { appid, code } = match match req.method() {
-                    &http::Method::GET => web::Query::<LoginRequest>::extract(req.request())
-                        .await
-                        .map(|v| v.0)
-                        .map_err(err_resp(400, "parse-get-params-fail", req.request())),
-                    &http::Method::POST => {
-                        let (request, payload) = req.parts_mut();
-                        web::Json::<LoginRequest>::from_request(request, payload)
-                            .await
-                            .map(|v| v.0)
-                            .map_err(err_resp(400, "parse-post-json-fail", request))
-                    }
-                    meth => Err(LoginError::from(meth.to_string())).map_err(err_resp(
-                        500,
-                        "unexpected-http-method",
-                        req.request(),
-                    )),
-                } {
-                    Ok(res) => res,
-                    Err(err) => {
-                        let resp = err.respond_to(req.request()).map_into_right_body();
-                        return Ok(ServiceResponse::new(req.into_parts().0, resp));
-                    }
-                };
-                myself
-                    .wx_login
-                    .handle_login(appid, code)
-                    .await
-                    .map(|v| v.respond_to(req.request()))
-                    .or_else(|v| Ok(v.respond_to(req.request())))
-                    .map(|v| ServiceResponse::new(req.into_parts().0, v.map_into_right_body()))
-            } else {
-                let header_stoken = req
-                    .headers()
-                    .get("WX-LOGIN-STOKEN")
-                    .ok_or(LoginError::from("no WX-LOGIN-STOKEN header"));
-                let stoken = header_stoken.and_then(|header_stoken| {
-                    header_stoken
-                        .to_str()
-                        .map_err(|e| LoginError::from(e.to_string()))
-                });
-                let auth_info: WxLoginAuthResult = stoken.and_then(|stoken| {
-                    let header_sig = req
-                        .headers()
-                        .get("WX-LOGIN-SIG")
-                        .ok_or(LoginError::from("no WX-LOGIN-SIG header"));
-                    let sig = header_sig.and_then(|header_sig| {
-                        header_sig
-                            .to_str()
-                            .map_err(|e| LoginError::from(e.to_string()))
-                    });
-                    myself
-                        .wx_login
-                        .authenticate(stoken, &req.uri().to_string(), sig)
-                });
-                req.extensions_mut().insert(auth_info);
-                myself
+            let cfg = myself.wx_login.cfg.load();
+            // the config is used to render error responses
+            req.extensions_mut().insert(cfg.clone());
+            // the login resources authenticate the requests by themselves, wherever they are mounted
+            if req
+                .match_name()
+                .is_some_and(|name| LOGIN_RESOURCE_NAMES.contains(&name))
+            {
+                return myself
                     .service
                     .call(req)
                     .await
-                    .map(|v| v.map_into_left_body())
+                    .map(|v| v.map_into_left_body());
             }
+            let body = match buffer_sig_body(&mut req, cfg.sig_body_limit).await {
+                Ok(body) => body,
+                Err(err) => {
+                    let resp = err.respond_to(req.request()).map_into_right_body();
+                    return Ok(ServiceResponse::new(req.into_parts().0, resp));
+                }
+            };
+            let uri = req.uri().to_string();
+            let sig_req = SigRequest::new(req.method().as_str(), &uri, &body);
+            let (stoken, sig) = auth_headers(req.headers());
+            let auth_info: WxLoginAuthResult = match stoken {
+                Ok(stoken) => myself.wx_login.authenticate(stoken, &sig_req, sig).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = &auth_info {
+                // match the path decoded by the router (e.g. `/%61pi` is routed as `/api`)
+                if cfg.auth_policy.requires_login(req.match_info().as_str()) {
+                    let resp = auth_err_resp(err.clone()).respond_to(req.request());
+                    return Ok(ServiceResponse::new(
+                        req.into_parts().0,
+                        resp.map_into_right_body(),
+                    ));
+                }
+            }
+            req.extensions_mut().insert(auth_info);
+            myself
+                .service
+                .call(req)
+                .await
+                .map(|v| v.map_into_left_body())
         })
     }
 }
 
+/// Handle the login request of `GET` (with query params) or `POST` (with json body).
+async fn login(wx_login: WxLogin, req: HttpRequest, payload: web::Payload) -> HttpResponse {
+    #[derive(Deserialize)]
+    struct LoginRequest {
+        appid: String,
+        code: String,
+    }
+
+    let cfg = wx_login.cfg.load();
+    // the config is used to render error responses
+    req.extensions_mut().insert(cfg.clone());
+    let login_req = match *req.method() {
+        http::Method::GET => web::Query::<LoginRequest>::extract(&req)
+            .await
+            .map(|v| v.0)
+            .map_err(err_resp(400, "parse-get-params-fail", &req)),
+        http::Method::POST => {
+            web::Json::<LoginRequest>::from_request(&req, &mut payload.into_inner())
+                .await
+                .map(|v| v.0)
+                .map_err(err_resp(400, "parse-post-json-fail", &req))
+        }
+        ref meth => Err(meth.to_string()).map_err(err_resp(500, "unexpected-http-method", &req)),
+    };
+    let LoginRequest { appid, code } = match login_req {
+        Ok(login_req) => login_req,
+        Err(resp) => return resp,
+    };
+    match wx_login
+        .handle_login_with_client(appid, code, client_info(&req, cfg.trust_proxy_headers))
+        .await
+    {
+        Ok(v) => v.respond_to(&req),
+        Err(v) => v.respond_to(&req),
+    }
+}
+
+/// Handle the refresh request authenticated by the stoken and sig headers.
+async fn refresh(wx_login: WxLogin, req: HttpRequest, payload: web::Payload) -> HttpResponse {
+    let cfg = wx_login.cfg.load();
+    // the config is used to render error responses
+    req.extensions_mut().insert(cfg.clone());
+    let is_sg2 = is_sg2(req.headers());
+    // the result of the middleware is reused if the resource is wrapped by it
+    let auth_res = req.extensions().get::<WxLoginAuthResult>().cloned();
+    let res = async {
+        let body = match is_sg2 {
+            true => read_body(payload, cfg.sig_body_limit).await?,
+            false => Bytes::new(),
+        };
+        let uri = req.uri().to_string();
+        let sig_req = SigRequest::new(req.method().as_str(), &uri, &body);
+        let (stoken, sig) = auth_headers(req.headers());
+        wx_login
+            .handle_refresh_with_auth(
+                auth_res,
+                stoken.map_err(auth_err_resp)?,
+                &sig_req,
+                sig,
+                client_info(&req, cfg.trust_proxy_headers),
+            )
+            .await
+    }
+    .await;
+    match res {
+        Ok(v) => v.respond_to(&req),
+        Err(v) => v.respond_to(&req),
+    }
+}
+
+/// Handle the logout request authenticated by the stoken and sig headers.
+async fn logout(wx_login: WxLogin, req: HttpRequest, payload: web::Payload) -> HttpResponse {
+    let cfg = wx_login.cfg.load();
+    // the config is used to render error responses
+    req.extensions_mut().insert(cfg.clone());
+    let is_sg2 = is_sg2(req.headers());
+    // the result of the middleware is reused if the resource is wrapped by it
+    let auth_res = req.extensions().get::<WxLoginAuthResult>().cloned();
+    let res = async {
+        let body = match is_sg2 {
+            true => read_body(payload, cfg.sig_body_limit).await?,
+            false => Bytes::new(),
+        };
+        let uri = req.uri().to_string();
+        let sig_req = SigRequest::new(req.method().as_str(), &uri, &body);
+        let (stoken, sig) = auth_headers(req.headers());
+        wx_login
+            .handle_logout_with_auth(auth_res, stoken.map_err(auth_err_resp)?, &sig_req, sig)
+            .await
+    }
+    .await;
+    match res {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(v) => v.respond_to(&req),
+    }
+}
+
+/// Get the client info, where the ip is taken from the peer address, or from the proxy headers if they are trusted.
+fn client_info(req: &HttpRequest, trust_proxy_headers: bool) -> ClientInfo {
+    let ip = match trust_proxy_headers {
+        true => req.connection_info().realip_remote_addr().map(String::from),
+        false => req.peer_addr().map(|v| v.ip().to_string()),
+    };
+    ClientInfo {
+        ip,
+        user_agent: req
+            .headers()
+            .get(http::header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from),
+    }
+}
+
+fn is_sg2(headers: &HeaderMap) -> bool {
+    headers
+        .get("WX-LOGIN-SIG")
+        .is_some_and(|v| v.as_bytes().starts_with(b"SG2:"))
+}
+
+/// Read the whole body within the limit.
+async fn read_body(
+    mut payload: impl Stream<Item = Result<Bytes, PayloadError>> + Unpin,
+    limit: usize,
+) -> Result<Bytes, WxLoginErr> {
+    let read_fail = |detail: String| WxLoginErr {
+        status: 400,
+        code: "read-request-body-fail".into(),
+        message: LOGIN_FAIL_MSG.into(),
+        detail,
+        server_time: None,
+    };
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| read_fail(e.to_string()))?;
+        if body.len() + chunk.len() > limit {
+            return Err(read_fail(format!("body is larger than {limit}")));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.freeze())
+}
+
+/// Buffer the request body if it is covered by the signature (i.e. SG2),
+/// and re-inject it into the request for the inner service.
+async fn buffer_sig_body(req: &mut ServiceRequest, limit: usize) -> Result<Bytes, WxLoginErr> {
+    if !is_sg2(req.headers()) {
+        return Ok(Bytes::new());
+    }
+    let body = read_body(req.take_payload(), limit).await?;
+    let stream: Pin<Box<dyn Stream<Item = Result<Bytes, PayloadError>>>> =
+        Box::pin(stream::once(ready(Ok(body.clone()))));
+    req.set_payload(Payload::from(stream));
+    Ok(body)
+}
+
+/// Get the (stoken, sig) from the headers of an authenticating request.
+fn auth_headers(headers: &HeaderMap) -> (Result<&str, LoginError>, Result<&str, LoginError>) {
+    let header_str =
+        |name: &str, err: LoginError| headers.get(name).and_then(|v| v.to_str().ok()).ok_or(err);
+    (
+        header_str("WX-LOGIN-STOKEN", LoginError::StokenMissing),
+        header_str("WX-LOGIN-SIG", LoginError::SigMissing),
+    )
+}
+
 fn err_resp<'a, E: Display>(
     status: u16,
     code: &'a str,
@@ -184,41 +396,51 @@ fn err_resp<'a, E: Display>(
             code: code.into(),
             message: LOGIN_FAIL_MSG.into(),
             detail: e.to_string(),
+            server_time: None,
         }
         .respond_to(req)
     }
 }
 
+fn extract_err(req: &HttpRequest) -> impl '_ + FnOnce(WxLoginRejection) -> WxLoginInfoError {
+    move |rejection| WxLoginInfoError {
+        rejection,
+        req: req.clone(),
+    }
+}
+
 impl FromRequest for WxLoginInfo {
-    type Error = Error;
+    type Error = WxLoginInfoError;
     type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
     fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
-        let req = req.clone();
-        Box::pin(async move {
-            match req.extensions().get::<WxLoginAuthResult>() {
-                Some(Ok(login_info)) => Ok(login_info.clone()),
-                Some(Err(err)) => Err(WrappedWxLoginErr {
-                    err: WxLoginErr {
-                        status: 401,
-                        code: "auth-login-session-fail".into(),
-                        message: AUTH_FAIL_MSG.into(),
-                        detail: err.to_string(),
-                    },
-                    req: req.clone(),
-                }
-                .into()),
-                None => Err(WrappedWxLoginErr {
-                    err: WxLoginErr {
-                        status: 500,
-                        code: "login-session-lost".into(),
-                        message: AUTH_FAIL_MSG.into(),
-                        detail: "".into(),
-                    },
-                    req: req.clone(),
-                }
-                .into()),
-            }
-        })
+        let res = extract_login_info(req.extensions().get::<WxLoginAuthResult>())
+            .map_err(extract_err(req));
+        Box::pin(ready(res))
+    }
+}
+
+impl FromRequest for OptionalWxLoginInfo {
+    type Error = WxLoginInfoError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        ready(
+            extract_optional_login_info(
+                req.extensions().get::<WxLoginAuthResult>(),
+                req.headers().contains_key("WX-LOGIN-STOKEN"),
+            )
+            .map_err(extract_err(req)),
+        )
+    }
+}
+
+impl FromRequest for SignedWxLoginInfo {
+    type Error = WxLoginInfoError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        ready(
+            extract_signed_login_info(req.extensions().get::<WxLoginAuthResult>())
+                .map_err(extract_err(req)),
+        )
     }
 }
 
@@ -233,32 +455,152 @@ impl Responder for WxLoginOk {
 impl Responder for WxLoginErr {
     type Body = BoxBody;
     fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
-        let status = self.status;
-        (web::Json(self), http::StatusCode::from_u16(status).unwrap())
+        let accept_language = req
+            .headers()
+            .get(http::header::ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok());
+        let rendered = render_error(
+            req.extensions().get::<Arc<Config>>().map(|v| v.as_ref()),
+            self,
+            accept_language,
+        );
+        let status = http::StatusCode::from_u16(rendered.status)
+            .unwrap_or(http::StatusCode::INTERNAL_SERVER_ERROR);
+        (web::Json(rendered.body), status)
             .respond_to(req)
             .map_into_boxed_body()
     }
 }
 
+/// The error of the [WxLoginInfo] extractors, which is rendered as the error response.
 #[derive(Debug)]
-struct WrappedWxLoginErr {
-    err: WxLoginErr,
+pub struct WxLoginInfoError {
+    /// The rejection with the error kind to branch on.
+    pub rejection: WxLoginRejection,
     req: HttpRequest,
 }
 
-impl Display for WrappedWxLoginErr {
+impl Display for WxLoginInfoError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("{:?}", self))
+        f.write_fmt(format_args!("{:?}", self.rejection))
     }
 }
-impl ResponseError for WrappedWxLoginErr {
+impl ResponseError for WxLoginInfoError {
     fn status_code(&self) -> http::StatusCode {
-        http::StatusCode::from_u16(self.err.status).unwrap()
+        http::StatusCode::from_u16(self.rejection.to_response().status)
+            .unwrap_or(http::StatusCode::INTERNAL_SERVER_ERROR)
     }
 
     fn error_response(&self) -> HttpResponse<BoxBody> {
-        (web::Json(self.err.clone()), self.status_code())
-            .respond_to(&self.req)
-            .map_into_boxed_body()
+        self.rejection.to_response().respond_to(&self.req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::session::MemorySessionStore;
+    use crate::core::testing::{fake_config, make_sg2_sig};
+    use actix_web::{test, App};
+
+    fn login_req(login_path: &str) -> test::TestRequest {
+        test::TestRequest::get().uri(&format!("{login_path}?appid=some_appid&code=good-code"))
+    }
+
+    fn login_tokens(login_ok: &serde_json::Value) -> (String, String) {
+        let field = |name: &str| login_ok[name].as_str().unwrap().to_string();
+        (field("stoken"), field("skey"))
+    }
+
+    fn signed_post(uri: &str, stoken: &str, skey: &str, body: &'static str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri(uri)
+            .insert_header(("WX-LOGIN-STOKEN", stoken))
+            .insert_header((
+                "WX-LOGIN-SIG",
+                make_sg2_sig(skey, "POST", uri, body.as_bytes()),
+            ))
+            .set_payload(body)
+    }
+
+    #[actix_web::test]
+    async fn sg2_body_reinjected() {
+        let mw = WxLoginMiddleware::new(fake_config().build());
+        let app = test::init_service(
+            App::new()
+                .wrap(mw.clone())
+                .service(mw.login_services())
+                .route(
+                    "/echo",
+                    web::post().to(|info: WxLoginInfo, body: String| async move {
+                        web::Json(serde_json::json!({ "openid": info.openid, "body": body }))
+                    }),
+                ),
+        )
+        .await;
+        let login_ok = test::call_and_read_body_json(&app, login_req("/login").to_request()).await;
+        let (stoken, skey) = login_tokens(&login_ok);
+        let req = signed_post("/echo", &stoken, &skey, "{\"a\":1}").to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["openid"], "some-openid");
+        assert_eq!(resp["body"], "{\"a\":1}");
+    }
+
+    #[actix_web::test]
+    async fn nested_login_scope() {
+        let mw = WxLoginMiddleware::new(
+            fake_config()
+                .with_login_required_by_default(true)
+                .with_session_store(MemorySessionStore::new())
+                .build(),
+        );
+        let app = test::init_service(
+            App::new()
+                .wrap(mw.clone())
+                .service(mw.login_scope("/auth"))
+                .route(
+                    "/echo",
+                    web::post().to(|info: WxLoginInfo| async move { info.openid.clone() }),
+                ),
+        )
+        .await;
+        let login_ok =
+            test::call_and_read_body_json(&app, login_req("/auth/login").to_request()).await;
+        let (stoken, skey) = login_tokens(&login_ok);
+        let req = signed_post("/auth/login/refresh", &stoken, &skey, "").to_request();
+        let login_ok = test::call_and_read_body_json(&app, req).await;
+        let (stoken, skey) = login_tokens(&login_ok);
+        let req = signed_post("/auth/login/logout", &stoken, &skey, "").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::NO_CONTENT);
+        let req = signed_post("/echo", &stoken, &skey, "").to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["code"], "session-revoked");
+    }
+
+    #[actix_web::test]
+    async fn client_ip_from_peer() {
+        let req = test::TestRequest::get()
+            .peer_addr("10.0.0.1:1234".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "1.1.1.1, 10.0.0.2"))
+            .to_http_request();
+        assert_eq!(client_info(&req, false).ip.as_deref(), Some("10.0.0.1"));
+        assert_eq!(client_info(&req, true).ip.as_deref(), Some("1.1.1.1"));
+    }
+
+    #[actix_web::test]
+    async fn policy_on_decoded_path() {
+        let mw = WxLoginMiddleware::new(fake_config().with_protected_path("/api/**").build());
+        let app = test::init_service(
+            App::new()
+                .wrap(mw)
+                .route("/api/secret", web::get().to(|| async { "secret" })),
+        )
+        .await;
+        for uri in ["/api/secret", "/%61pi/secret"] {
+            let resp =
+                test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED, "{uri}");
+        }
     }
 }
@@ -1,36 +1,49 @@
 use axum::{
     async_trait,
-    extract::{FromRequest, FromRequestParts, Query, Request},
-    http::{request::Parts, Method, StatusCode},
+    body::{to_bytes, Body, Bytes},
+    extract::{ConnectInfo, FromRequest, FromRequestParts, OriginalUri, Query, Request},
+    http::{
+        header::{ACCEPT_LANGUAGE, USER_AGENT},
+        request::Parts,
+        HeaderMap, Method, StatusCode, Uri,
+    },
     response::{IntoResponse, Response},
-    Json,
+    routing::{get, post, MethodRouter},
+    Json, Router,
 };
-use futures_util::{future::BoxFuture, TryFutureExt};
+use futures_util::future::BoxFuture;
 use serde::Deserialize;
 use std::{
     convert::Infallible,
     fmt::Display,
-    sync::Arc,
+    net::SocketAddr,
     task::{Context, Poll},
 };
 use tower::{Layer, Service};
 
 use crate::core::{
     config::{Config, ConfigBuilder},
-    login::{Error, WxLogin, WxLoginErr, WxLoginInfo, WxLoginOk, AUTH_FAIL_MSG, LOGIN_FAIL_MSG},
+    login::{
+        auth_err_resp, extract_login_info, extract_optional_login_info, extract_signed_login_info,
+        Error, OptionalWxLoginInfo, SignedWxLoginInfo, WxLogin, WxLoginAuthResult, WxLoginErr,
+        WxLoginInfo, WxLoginOk, WxLoginRejection, LOGIN_FAIL_MSG,
+    },
+    reload::ConfigHandle,
+    render::{render_error, RenderedError},
+    security::SigRequest,
+    session::ClientInfo,
 };
 
-type WxLoginAuthResult = Result<WxLoginInfo, Error>;
-
 /// Create a [WxLoginLayer] with config derived from environment variables and default values.
 pub fn layer_with_env_var() -> WxLoginLayer {
     WxLoginLayer::new_with_env_var()
 }
 
-/// An axum layer (middleware) wrapping the functionality of this crate.
+/// An axum layer (middleware) authenticating the requests,
+/// whose login, refresh and logout handlers are mounted by [login_router](WxLoginLayer::login_router).
 #[derive(Clone)]
 pub struct WxLoginLayer {
-    cfg: Arc<Config>,
+    cfg: ConfigHandle,
 }
 
 impl WxLoginLayer {
@@ -41,7 +54,61 @@ impl WxLoginLayer {
 
     /// Create a [WxLoginLayer] with specified config.
     pub fn new(cfg: Config) -> Self {
-        Self { cfg: Arc::new(cfg) }
+        Self { cfg: cfg.into() }
+    }
+
+    /// Create a [WxLoginLayer] with a reloadable [ConfigHandle].
+    pub fn with_config_handle(cfg: ConfigHandle) -> Self {
+        Self { cfg }
+    }
+
+    /// Get a router of the login, refresh and logout handlers at the paths of config,
+    /// which can be merged or nested into the app router.
+    ///
+    /// When merged, the layer passes the requests of the paths through even if it wraps the router.
+    /// When nested under a prefix, the router should be added after the layer is applied, so that
+    /// the handlers are not wrapped by it (axum applies a layer only to the routes added before).
+    /// If they are wrapped anyway, the refresh and logout handlers reuse the result of the layer,
+    /// but the auth policy applies to them like any other route.
+    ///
+    /// Note that the paths are fixed when the router is created, even if the config is reloaded.
+    pub fn login_router<S>(&self) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let cfg = self.cfg.load();
+        Router::new()
+            .route(&cfg.login_path, self.login_handler())
+            .route(&cfg.refresh_path, self.refresh_handler())
+            .route(&cfg.logout_path, self.logout_handler())
+    }
+
+    /// Get the login handler (`GET|POST`), which can be mounted at any path.
+    pub fn login_handler<S>(&self) -> MethodRouter<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let wx_login = WxLogin::new(self.cfg.clone());
+        let handler = move |req: Request| login(wx_login.clone(), req);
+        get(handler.clone()).post(handler)
+    }
+
+    /// Get the refresh handler (`POST`), which can be mounted at any path.
+    pub fn refresh_handler<S>(&self) -> MethodRouter<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let wx_login = WxLogin::new(self.cfg.clone());
+        post(move |req: Request| refresh(wx_login.clone(), req))
+    }
+
+    /// Get the logout handler (`POST`), which can be mounted at any path.
+    pub fn logout_handler<S>(&self) -> MethodRouter<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let wx_login = WxLogin::new(self.cfg.clone());
+        post(move |req: Request| logout(wx_login.clone(), req))
     }
 }
 
@@ -77,72 +144,194 @@ where
         self.inner.poll_ready(cx).map(|r| r.or(Ok(())))
     }
 
-    fn call(&mut self, mut req: Request) -> Self::Future {
-        #[derive(Deserialize)]
-        struct LoginRequest {
-            appid: String,
-            code: String,
-        }
-
+    fn call(&mut self, req: Request) -> Self::Future {
         let mut myself = self.clone();
 
-        Box::pin(
-            async move {
-                if req.uri().path() == "/login" {
-                    let LoginRequest { appid, code } = match req.method() {
-                        &Method::GET => {
-                            Query::<LoginRequest>::try_from_uri(req.uri())
-                                .map_err(err_resp(400, "parse-get-params-fail"))?
-                                .0
-                        }
-                        &Method::POST => {
-                            Json::<LoginRequest>::from_request(req, &())
-                                .await
-                                .map_err(err_resp(400, "parse-post-json-fail"))?
-                                .0
-                        }
-                        meth => Err(Error::from(meth.to_string()))
-                            .map_err(err_resp(500, "unexpected-http-method"))?,
-                    };
-                    myself
-                        .wx_login
-                        .handle_login(appid, code)
-                        .await
-                        .map(|v| v.into_response())
-                        .map_err(|v| v.into_response())
-                } else {
-                    let header_stoken = req
-                        .headers()
-                        .get("WX-LOGIN-STOKEN")
-                        .ok_or(Error::from("no WX-LOGIN-STOKEN header"));
-                    let stoken = header_stoken.and_then(|header_stoken| {
-                        header_stoken
-                            .to_str()
-                            .map_err(|e| Error::from(e.to_string()))
-                    });
-                    let auth_info: WxLoginAuthResult = stoken.and_then(|stoken| {
-                        let header_sig = req
-                            .headers()
-                            .get("WX-LOGIN-SIG")
-                            .ok_or(Error::from("no WX-LOGIN-SIG header"));
-                        let sig = header_sig.and_then(|header_sig| {
-                            header_sig
-                                .to_str()
-                                .map_err(|e| Error::from(e.to_string()))
-                        });
-                        myself.wx_login.authenticate(stoken, &req.uri().to_string(), sig)
-                    });
-                    req.extensions_mut().insert(auth_info);
-                    myself
+        Box::pin(async move {
+            let cfg = myself.wx_login.cfg.load();
+            let accept_language = accept_language(req.headers());
+            let res: Result<Response, Response> = async {
+                // the handlers of login_router authenticate the requests by themselves
+                let path = original_uri(&req).path();
+                if path == cfg.login_path || path == cfg.refresh_path || path == cfg.logout_path {
+                    return myself
                         .inner
                         .call(req)
                         .await
-                        .map_err(err_resp(500, "inner-service-fail"))
+                        .map_err(err_resp(500, "inner-service-fail"));
                 }
+                let (mut req, body) = buffer_sig_body(req, cfg.sig_body_limit).await?;
+                let uri = original_uri(&req).to_string();
+                let sig_req = SigRequest::new(req.method().as_str(), &uri, &body);
+                let (stoken, sig) = auth_headers(req.headers());
+                let auth_info: WxLoginAuthResult = match stoken {
+                    Ok(stoken) => myself.wx_login.authenticate(stoken, &sig_req, sig).await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = &auth_info {
+                    if cfg.auth_policy.requires_login(original_uri(&req).path()) {
+                        return Err(auth_err_resp(err.clone()).into_response());
+                    }
+                }
+                req.extensions_mut().insert(auth_info);
+                myself
+                    .inner
+                    .call(req)
+                    .await
+                    .map_err(err_resp(500, "inner-service-fail"))
             }
-            .or_else(|error_resp| async move { Ok(error_resp) }),
-        )
+            .await;
+            Ok(render_err_resp(
+                &cfg,
+                accept_language.as_deref(),
+                res.unwrap_or_else(|resp| resp),
+            ))
+        })
+    }
+}
+
+/// Handle the login request of `GET` (with query params) or `POST` (with json body).
+async fn login(wx_login: WxLogin, req: Request) -> Response {
+    #[derive(Deserialize)]
+    struct LoginRequest {
+        appid: String,
+        code: String,
+    }
+
+    let cfg = wx_login.cfg.load();
+    let accept_language = accept_language(req.headers());
+    let client = client_info(&req, cfg.trust_proxy_headers);
+    let res = async {
+        let LoginRequest { appid, code } = match req.method() {
+            &Method::GET => {
+                Query::<LoginRequest>::try_from_uri(req.uri())
+                    .map_err(err_resp(400, "parse-get-params-fail"))?
+                    .0
+            }
+            &Method::POST => {
+                Json::<LoginRequest>::from_request(req, &())
+                    .await
+                    .map_err(err_resp(400, "parse-post-json-fail"))?
+                    .0
+            }
+            meth => Err(meth.to_string()).map_err(err_resp(500, "unexpected-http-method"))?,
+        };
+        wx_login
+            .handle_login_with_client(appid, code, client)
+            .await
+            .map(|v| v.into_response())
+            .map_err(|v| v.into_response())
+    }
+    .await;
+    render_err_resp(&cfg, accept_language.as_deref(), res.unwrap_or_else(|v| v))
+}
+
+/// Handle the refresh request authenticated by the stoken and sig headers.
+async fn refresh(wx_login: WxLogin, req: Request) -> Response {
+    let cfg = wx_login.cfg.load();
+    let accept_language = accept_language(req.headers());
+    let client = client_info(&req, cfg.trust_proxy_headers);
+    let res = async {
+        // the result of the layer is reused if the handler is wrapped by it
+        let auth_res = req.extensions().get::<WxLoginAuthResult>().cloned();
+        let (req, body) = buffer_sig_body(req, cfg.sig_body_limit).await?;
+        let uri = original_uri(&req).to_string();
+        let sig_req = SigRequest::new(req.method().as_str(), &uri, &body);
+        let (stoken, sig) = auth_headers(req.headers());
+        let stoken = stoken.map_err(|e| auth_err_resp(e).into_response())?;
+        wx_login
+            .handle_refresh_with_auth(auth_res, stoken, &sig_req, sig, client)
+            .await
+            .map(|v| v.into_response())
+            .map_err(|v| v.into_response())
+    }
+    .await;
+    render_err_resp(&cfg, accept_language.as_deref(), res.unwrap_or_else(|v| v))
+}
+
+/// Handle the logout request authenticated by the stoken and sig headers.
+async fn logout(wx_login: WxLogin, req: Request) -> Response {
+    let cfg = wx_login.cfg.load();
+    let accept_language = accept_language(req.headers());
+    let res = async {
+        // the result of the layer is reused if the handler is wrapped by it
+        let auth_res = req.extensions().get::<WxLoginAuthResult>().cloned();
+        let (req, body) = buffer_sig_body(req, cfg.sig_body_limit).await?;
+        let uri = original_uri(&req).to_string();
+        let sig_req = SigRequest::new(req.method().as_str(), &uri, &body);
+        let (stoken, sig) = auth_headers(req.headers());
+        let stoken = stoken.map_err(|e| auth_err_resp(e).into_response())?;
+        wx_login
+            .handle_logout_with_auth(auth_res, stoken, &sig_req, sig)
+            .await
+            .map(|_| StatusCode::NO_CONTENT.into_response())
+            .map_err(|v| v.into_response())
+    }
+    .await;
+    render_err_resp(&cfg, accept_language.as_deref(), res.unwrap_or_else(|v| v))
+}
+
+/// Get the uri before being stripped by nested routers, which is the uri signed by client.
+fn original_uri(req: &Request) -> &Uri {
+    req.extensions()
+        .get::<OriginalUri>()
+        .map(|v| &v.0)
+        .unwrap_or(req.uri())
+}
+
+/// Get the client info, where the ip is taken from [ConnectInfo] (requires `into_make_service_with_connect_info`),
+/// or from the proxy headers if they are trusted.
+fn client_info(req: &Request, trust_proxy_headers: bool) -> ClientInfo {
+    let header_str = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok());
+    let proxy_ip = || {
+        header_str("X-Forwarded-For")
+            .and_then(|v| v.split(',').next())
+            .or_else(|| header_str("X-Real-IP"))
+            .map(|v| v.trim().to_string())
+    };
+    let ip = trust_proxy_headers.then(proxy_ip).flatten().or_else(|| {
+        req.extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|v| v.0.ip().to_string())
+    });
+    ClientInfo {
+        ip,
+        user_agent: header_str(USER_AGENT.as_str()).map(String::from),
+    }
+}
+
+fn accept_language(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+/// Buffer the request body if it is covered by the signature (i.e. SG2),
+/// and re-inject it into the request for the inner service.
+async fn buffer_sig_body(req: Request, limit: usize) -> Result<(Request, Bytes), Response> {
+    let is_sg2 = req
+        .headers()
+        .get("WX-LOGIN-SIG")
+        .is_some_and(|v| v.as_bytes().starts_with(b"SG2:"));
+    if !is_sg2 {
+        return Ok((req, Bytes::new()));
     }
+    let (parts, body) = req.into_parts();
+    let body = to_bytes(body, limit)
+        .await
+        .map_err(err_resp(400, "read-request-body-fail"))?;
+    Ok((Request::from_parts(parts, Body::from(body.clone())), body))
+}
+
+/// Get the (stoken, sig) from the headers of an authenticating request.
+fn auth_headers(headers: &HeaderMap) -> (Result<&str, Error>, Result<&str, Error>) {
+    let header_str =
+        |name: &str, err: Error| headers.get(name).and_then(|v| v.to_str().ok()).ok_or(err);
+    (
+        header_str("WX-LOGIN-STOKEN", Error::StokenMissing),
+        header_str("WX-LOGIN-SIG", Error::SigMissing),
+    )
 }
 
 fn err_resp<E: Display>(status: u16, code: &str) -> impl '_ + FnOnce(E) -> Response {
@@ -152,13 +341,14 @@ fn err_resp<E: Display>(status: u16, code: &str) -> impl '_ + FnOnce(E) -> Respo
             code: code.into(),
             message: LOGIN_FAIL_MSG.into(),
             detail: e.to_string(),
+            server_time: None,
         }
         .into_response()
     }
 }
 
-/// The rejection type of the [WxLoginInfo] extractor.
-pub type WxLoginInfoRejection = WxLoginErr;
+/// The rejection type of the [WxLoginInfo] extractor and its variants.
+pub type WxLoginInfoRejection = WxLoginRejection;
 
 #[async_trait]
 impl<S> FromRequestParts<S> for WxLoginInfo
@@ -168,21 +358,34 @@ where
     type Rejection = WxLoginInfoRejection;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        match parts.extensions.get::<WxLoginAuthResult>() {
-            Some(Ok(login_info)) => Ok(login_info.clone()),
-            Some(Err(err)) => Err(WxLoginErr {
-                status: 401,
-                code: "auth-login-session-fail".into(),
-                message: AUTH_FAIL_MSG.into(),
-                detail: err.to_string(),
-            }),
-            None => Err(WxLoginErr {
-                status: 500,
-                code: "login-session-lost".into(),
-                message: AUTH_FAIL_MSG.into(),
-                detail: "".into(),
-            }),
-        }
+        extract_login_info(parts.extensions.get::<WxLoginAuthResult>())
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for OptionalWxLoginInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = WxLoginInfoRejection;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        extract_optional_login_info(
+            parts.extensions.get::<WxLoginAuthResult>(),
+            parts.headers.contains_key("WX-LOGIN-STOKEN"),
+        )
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SignedWxLoginInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = WxLoginInfoRejection;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        extract_signed_login_info(parts.extensions.get::<WxLoginAuthResult>())
     }
 }
 
@@ -191,8 +394,167 @@ impl IntoResponse for WxLoginOk {
         (StatusCode::OK, Json(self)).into_response()
     }
 }
+impl IntoResponse for WxLoginRejection {
+    fn into_response(self) -> Response {
+        self.to_response().into_response()
+    }
+}
 impl IntoResponse for WxLoginErr {
     fn into_response(self) -> Response {
-        (StatusCode::from_u16(self.status).unwrap(), Json(self)).into_response()
+        // the error is kept in extensions to be rendered by the config of the layer
+        let mut resp = rendered_resp(render_error(None, self.clone(), None));
+        resp.extensions_mut().insert(self);
+        resp
+    }
+}
+
+fn rendered_resp(rendered: RenderedError) -> Response {
+    let status = StatusCode::from_u16(rendered.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    (status, Json(rendered.body)).into_response()
+}
+
+/// Render the response of [WxLoginErr] with the error renderer of config.
+fn render_err_resp(cfg: &Config, accept_language: Option<&str>, mut resp: Response) -> Response {
+    match resp.extensions_mut().remove::<WxLoginErr>() {
+        Some(err) => rendered_resp(render_error(Some(cfg), err, accept_language)),
+        None => resp,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::session::MemorySessionStore;
+    use crate::core::testing::{fake_config, make_sg2_sig};
+    use tower::ServiceExt;
+
+    async fn send(app: &Router, req: Request) -> (StatusCode, serde_json::Value) {
+        let resp = app.clone().oneshot(req).await.unwrap();
+        let status = resp.status();
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    async fn login(app: &Router, login_path: &str) -> (String, String) {
+        let req = Request::get(format!("{login_path}?appid=some_appid&code=good-code"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, login_ok) = send(app, req).await;
+        assert_eq!(status, StatusCode::OK, "{login_ok}");
+        let field = |name: &str| login_ok[name].as_str().unwrap().to_string();
+        (field("stoken"), field("skey"))
+    }
+
+    fn signed_post(uri: &str, stoken: &str, skey: &str, body: &'static str) -> Request {
+        Request::post(uri)
+            .header("WX-LOGIN-STOKEN", stoken)
+            .header(
+                "WX-LOGIN-SIG",
+                make_sg2_sig(skey, "POST", uri, body.as_bytes()),
+            )
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn sg2_body_reinjected() {
+        let layer = WxLoginLayer::new(fake_config().build());
+        let app = Router::new()
+            .route(
+                "/echo",
+                post(|info: WxLoginInfo, body: String| async move {
+                    Json(serde_json::json!({ "openid": info.openid, "body": body }))
+                }),
+            )
+            .merge(layer.login_router())
+            .layer(layer);
+        let (stoken, skey) = login(&app, "/login").await;
+        let (status, resp) = send(&app, signed_post("/echo", &stoken, &skey, "{\"a\":1}")).await;
+        assert_eq!(status, StatusCode::OK, "{resp}");
+        assert_eq!(resp["openid"], "some-openid");
+        assert_eq!(resp["body"], "{\"a\":1}");
+    }
+
+    #[tokio::test]
+    async fn merged_login_router() {
+        let layer = WxLoginLayer::new(
+            fake_config()
+                .with_login_required_by_default(true)
+                .with_session_store(MemorySessionStore::new())
+                .build(),
+        );
+        let app = Router::new()
+            .route(
+                "/echo",
+                post(|info: WxLoginInfo| async move { info.openid.clone() }),
+            )
+            .merge(layer.login_router())
+            .layer(layer);
+        let (stoken, skey) = login(&app, "/login").await;
+        let (status, login_ok) =
+            send(&app, signed_post("/login/refresh", &stoken, &skey, "")).await;
+        assert_eq!(status, StatusCode::OK, "{login_ok}");
+        let stoken = login_ok["stoken"].as_str().unwrap();
+        let skey = login_ok["skey"].as_str().unwrap();
+        let resp = app
+            .clone()
+            .oneshot(signed_post("/login/logout", stoken, skey, ""))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let req = Request::post("/echo").body(Body::empty()).unwrap();
+        assert_eq!(send(&app, req).await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn nested_login_router() {
+        let layer = WxLoginLayer::new(
+            fake_config()
+                .with_session_store(MemorySessionStore::new())
+                .build(),
+        );
+        let echo = post(|info: WxLoginInfo| async move { info.openid.clone() });
+        // the handlers wrapped by the layer reuse its result instead of checking the nonce again
+        let app = Router::new()
+            .route("/echo", echo.clone())
+            .nest("/auth", layer.login_router())
+            .layer(layer.clone());
+        let (stoken, skey) = login(&app, "/auth/login").await;
+        let req = signed_post("/auth/login/refresh", &stoken, &skey, "");
+        let (status, login_ok) = send(&app, req).await;
+        assert_eq!(status, StatusCode::OK, "{login_ok}");
+        let stoken = login_ok["stoken"].as_str().unwrap();
+        let skey = login_ok["skey"].as_str().unwrap();
+        let req = signed_post("/auth/login/logout", stoken, skey, "");
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let (status, resp) = send(&app, signed_post("/echo", stoken, skey, "")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(resp["code"], "session-revoked");
+
+        // the handlers added after the layer are not wrapped by it
+        let layer = WxLoginLayer::new(fake_config().with_login_required_by_default(true).build());
+        let app = Router::new()
+            .route("/echo", echo)
+            .layer(layer.clone())
+            .nest("/auth", layer.login_router());
+        let (stoken, skey) = login(&app, "/auth/login").await;
+        let req = signed_post("/auth/login/refresh", &stoken, &skey, "");
+        let (status, login_ok) = send(&app, req).await;
+        assert_eq!(status, StatusCode::OK, "{login_ok}");
+        let req = Request::post("/echo").body(Body::empty()).unwrap();
+        assert_eq!(send(&app, req).await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn client_ip_from_peer() {
+        let mut req = Request::get("/login")
+            .header("X-Forwarded-For", "1.1.1.1, 10.0.0.2")
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 1234))));
+        assert_eq!(client_info(&req, false).ip.as_deref(), Some("10.0.0.1"));
+        assert_eq!(client_info(&req, true).ip.as_deref(), Some("1.1.1.1"));
     }
 }
@@ -1,12 +1,32 @@
-use std::collections::HashMap;
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
-use crate::core::security::secret_utils::SecretString;
+use itertools::Itertools;
+
+use crate::core::{
+    config_file::{self, ConfigFile},
+    dedup::LoginDedup,
+    hook::LoginHook,
+    nonce::{MemoryNonceStore, NonceStore},
+    policy::AuthPolicy,
+    provider::{AppInfoProvider, StaticFirstAppInfoProvider},
+    render::{DefaultErrorRenderer, ErrorRenderer},
+    security::{secret_utils::SecretString, Error, SigScheme, TokenKeyRing},
+    session::{MemorySessionStore, SessionStore},
+    wx_api::{HttpClientOptions, ReqwestWxApiClient, WxApiClient},
+};
 
 /// Basic data (app-id, app-secret) of a WeChat mini-program.
 #[derive(Default, Debug, Clone)]
 pub struct AppInfo {
     pub(crate) appid: String,
     pub(crate) secret: SecretString,
+    pub(crate) fallback_secret: Option<SecretString>,
 }
 impl AppInfo {
     /// Create a new AppInfo from (appid, secret).
@@ -14,39 +34,197 @@ impl AppInfo {
         Self {
             appid,
             secret: SecretString(secret),
+            fallback_secret: None,
         }
     }
+    /// Set the fallback secret, which is used when WeChat rejects the primary secret (errcode 40125).
+    ///
+    /// It helps to keep login working while the app secret is being reset in the WeChat console.
+    pub fn with_fallback_secret(mut self, secret: String) -> Self {
+        self.fallback_secret = Some(SecretString(secret));
+        self
+    }
 }
 
 /// Configuration of the crate.
 #[derive(Debug, Clone)]
 pub struct Config {
-    pub(crate) app_map: HashMap<String, AppInfo>,
+    pub(crate) app_map: Arc<HashMap<String, AppInfo>>,
+    /// The provider of all app lookups, which is the static app map by default.
+    pub(crate) app_provider: Arc<dyn AppInfoProvider>,
     pub(crate) login_path: String,
+    pub(crate) refresh_path: String,
+    pub(crate) logout_path: String,
     pub(crate) auth_sig: bool,
+    pub(crate) auth_policy: AuthPolicy,
     pub(crate) sig_valid_secs: u64,
+    pub(crate) sig_future_skew_secs: u64,
+    pub(crate) sig_schemes: Vec<SigScheme>,
+    pub(crate) sig_body_limit: usize,
+    pub(crate) nonce_store: Option<Arc<dyn NonceStore>>,
+    pub(crate) session_max_age: Option<Duration>,
+    pub(crate) refresh_max_age: Option<Duration>,
+    pub(crate) session_store: Option<Arc<dyn SessionStore>>,
+    pub(crate) max_sessions_per_openid: Option<usize>,
+    pub(crate) st1_accept_until: Option<SystemTime>,
+    pub(crate) token_keys: TokenKeyRing,
+    /// The api client, which is built once by [ConfigBuilder::build].
+    pub(crate) api_client: Option<Arc<dyn WxApiClient>>,
+    pub(crate) api_options: HttpClientOptions,
+    pub(crate) in_memory: InMemoryParts,
+    pub(crate) login_hook: Option<Arc<dyn LoginHook>>,
+    pub(crate) login_dedup: Arc<LoginDedup>,
+    pub(crate) error_renderer: Arc<dyn ErrorRenderer>,
+    pub(crate) trust_proxy_headers: bool,
+    pub(crate) production: bool,
 }
 impl Default for Config {
     fn default() -> Self {
+        ConfigBuilder::new().build()
+    }
+}
+impl Config {
+    /// The default config values without the api client, which are completed by the builder.
+    fn unbuilt() -> Self {
         Self {
             app_map: Default::default(),
+            app_provider: Arc::new(HashMap::<String, AppInfo>::new()),
             login_path: "/login".into(),
+            refresh_path: "/login/refresh".into(),
+            logout_path: "/login/logout".into(),
             auth_sig: true,
+            auth_policy: Default::default(),
             sig_valid_secs: 600,
+            sig_future_skew_secs: 60,
+            sig_schemes: vec![SigScheme::SG1, SigScheme::SG2],
+            sig_body_limit: 2 * 1024 * 1024,
+            nonce_store: Some(Arc::new(MemoryNonceStore::new())),
+            session_max_age: None,
+            refresh_max_age: None,
+            session_store: None,
+            max_sessions_per_openid: None,
+            st1_accept_until: None,
+            token_keys: Default::default(),
+            api_client: None,
+            api_options: Default::default(),
+            in_memory: InMemoryParts {
+                nonce_store: true,
+                ..Default::default()
+            },
+            login_hook: None,
+            login_dedup: Arc::new(LoginDedup::new(Duration::from_secs(5))),
+            error_renderer: Arc::new(DefaultErrorRenderer::new()),
+            trust_proxy_headers: false,
+            production: false,
         }
     }
 }
+/// The built-in parts of a config whose state is in memory (e.g. the seen nonces), which are
+/// carried over to the new config on reload.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct InMemoryParts {
+    nonce_store: bool,
+    session_store: bool,
+    api_client: Option<ReqwestWxApiClient>,
+}
+
 impl Config {
     /// Get the ConfigBuilder
     pub fn builder() -> ConfigBuilder {
         ConfigBuilder::new()
     }
+
+    /// Get the AppInfo of the appid from the app provider.
+    pub(crate) async fn get_app_info(&self, appid: &str) -> Result<Option<AppInfo>, Error> {
+        self.app_provider.get_app_info(appid).await
+    }
+
+    /// Get the api client, which every config gets from [ConfigBuilder::build].
+    pub(crate) fn api_client(&self) -> &Arc<dyn WxApiClient> {
+        self.api_client
+            .as_ref()
+            .expect("api client is built by ConfigBuilder::build")
+    }
+
+    /// Carry over the state in memory from the old config, which is lost by a reload otherwise,
+    /// i.e. the built-in nonce store, session store, api client (with its circuit breaker
+    /// and concurrency limit) and login dedup cache, if the new config uses the same kind.
+    pub(crate) fn inherit_state(&mut self, old: &Config) {
+        if self.in_memory.nonce_store && old.in_memory.nonce_store {
+            self.nonce_store.clone_from(&old.nonce_store);
+        }
+        if self.in_memory.session_store && old.in_memory.session_store {
+            self.session_store.clone_from(&old.session_store);
+        }
+        if let (Some(client), Some(old_client)) =
+            (&mut self.in_memory.api_client, &old.in_memory.api_client)
+        {
+            client.inherit_state(old_client);
+            self.api_client = Some(Arc::new(client.clone()));
+        }
+        if self.login_dedup.ttl() == old.login_dedup.ttl() {
+            self.login_dedup = old.login_dedup.clone();
+        }
+    }
+
+    /// Check the config values, which is required before a config is reloaded.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        let paths = [&self.login_path, &self.refresh_path, &self.logout_path];
+        for path in paths {
+            if !path.starts_with('/') {
+                return Err(Error::Config(format!("path must start with '/': {path}")));
+            }
+        }
+        if let Some(pattern) = self.auth_policy.patterns().find(|v| !v.starts_with('/')) {
+            return Err(Error::Config(format!(
+                "path pattern must start with '/': {pattern}"
+            )));
+        }
+        if paths.iter().duplicates().next().is_some() {
+            return Err(Error::Config(
+                "login path, refresh path and logout path must be different".into(),
+            ));
+        }
+        if let Some(app_info) = self.app_map.values().find(|v| v.secret.0.is_empty()) {
+            return Err(Error::Config(format!(
+                "empty secret of app {}",
+                app_info.appid
+            )));
+        }
+        if self.auth_sig && self.sig_schemes.is_empty() {
+            return Err(Error::Config("no sig scheme is allowed".into()));
+        }
+        if self.max_sessions_per_openid == Some(0) {
+            return Err(Error::Config(
+                "max sessions per openid must be positive".into(),
+            ));
+        }
+        if self.api_options.max_concurrency == Some(0) {
+            return Err(Error::Config("api max concurrency must be positive".into()));
+        }
+        if self.auth_sig && self.sig_valid_secs == 0 {
+            return Err(Error::Config("sig valid secs must be positive".into()));
+        }
+        Ok(())
+    }
 }
 
 /// A builder for make custumized Config.
-#[derive(Default)]
 pub struct ConfigBuilder {
-    cfg: Config,
+    pub(crate) cfg: Config,
+    /// The custom api client, otherwise a [ReqwestWxApiClient] is built from the api options.
+    api_client: Option<Arc<dyn WxApiClient>>,
+    /// The custom app provider, which is consulted for the appids not in the static app map.
+    app_provider: Option<Arc<dyn AppInfoProvider>>,
+}
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        Self {
+            cfg: Config::unbuilt(),
+            api_client: None,
+            app_provider: None,
+        }
+    }
 }
 impl ConfigBuilder {
     /// Create a ConfigBuilder with default configuration.
@@ -54,49 +232,376 @@ impl ConfigBuilder {
         Default::default()
     }
     fn add_app_info(&mut self, app_info: AppInfo) {
-        self.cfg.app_map.insert(app_info.appid.clone(), app_info);
+        Arc::make_mut(&mut self.cfg.app_map).insert(app_info.appid.clone(), app_info);
     }
     /// Add one entry of app
     pub fn with_app_info(mut self, app_info: AppInfo) -> Self {
         self.add_app_info(app_info);
         self
     }
-    /// Load app entries from environment varibles.
-    /// 
+    /// Load config values from a TOML file (or JSON file with the extension .json).
+    ///
+    /// Secrets can be read from files (e.g. mounted Docker/Kubernetes secrets) using
+    /// the *_file fields:
+    ///
+    /// ```toml
+    /// login_path = "/login"
+    /// auth_sig = true
+    /// sig_valid_secs = 600
+    /// session_max_age_secs = 604800
+    ///
+    /// [[apps]]
+    /// appid = "TheAppID"
+    /// secret_file = "/run/secrets/wx_app_secret"
+    /// ```
+    pub fn with_file(self, path: impl AsRef<Path>) -> Result<Self, Error> {
+        ConfigFile::load(path.as_ref())?.apply(self)
+    }
+    /// Load app entries and config values from environment varibles.
+    ///
     /// All environment variables with prefix of WX_APP_ will be parsed as WX_APP_\<app-id\> = \<app-secret\>
-    /// and be loaded as app-info.
-    pub fn with_env_var(mut self) -> Self {
-        const PREFIX: &str = "WX_APP_";
-        std::env::vars()
-            .filter(|(k, _)| k.starts_with(PREFIX))
-            .for_each(|(k, v)| {
-                self.add_app_info(AppInfo::from(k[PREFIX.len()..].into(), v));
-            });
+    /// and be loaded as app-info. The config values are loaded from WX_LOGIN_\<NAME\> variables
+    /// (e.g. WX_LOGIN_LOGIN_PATH, WX_LOGIN_AUTH_SIG, WX_LOGIN_SIG_VALID_SECS), which override
+    /// the values loaded before (e.g. by [with_file](Self::with_file)). The list values
+    /// (WX_LOGIN_PROTECTED_PATHS, WX_LOGIN_PUBLIC_PATHS and the WX_LOGIN_TOKEN_KEY_\<id\> keys)
+    /// replace the loaded lists, while the apps are overridden by appid.
+    ///
+    /// Any variable can be replaced with a \<NAME\>_FILE variable, whose value is the path of
+    /// a file containing the value (e.g. WX_APP_\<app-id\>_FILE = /run/secrets/wx_app_secret).
+    ///
+    /// The invalid variables are logged and skipped, see [try_with_env_var](Self::try_with_env_var).
+    pub fn with_env_var(self) -> Self {
+        config_file::apply_env(self, false).expect("env vars are not strict")
+    }
+    /// Same as [with_env_var](Self::with_env_var) but returns the error on invalid variables.
+    pub fn try_with_env_var(self) -> Result<Self, Error> {
+        config_file::apply_env(self, true)
+    }
+    /// Set the provider of apps not in the static app map (e.g. loaded from a database).
+    ///
+    /// The static app map (set by [with_app_info](Self::with_app_info) etc.) is looked up first.
+    /// By default there is no provider.
+    pub fn with_app_provider(mut self, provider: impl AppInfoProvider + 'static) -> Self {
+        self.app_provider = Some(Arc::new(provider));
         self
     }
     /// Set the login path
-    /// 
+    ///
     /// The default value is "/login", one can override the path value.
     pub fn with_login_path(mut self, path: &str) -> Self {
         self.cfg.login_path = path.into();
         self
     }
+    /// Set the refresh path
+    ///
+    /// The default value is "/login/refresh", one can override the path value.
+    pub fn with_refresh_path(mut self, path: &str) -> Self {
+        self.cfg.refresh_path = path.into();
+        self
+    }
+    /// Set the logout path
+    ///
+    /// The default value is "/login/logout", one can override the path value.
+    pub fn with_logout_path(mut self, path: &str) -> Self {
+        self.cfg.logout_path = path.into();
+        self
+    }
     /// Enable or disable signature authentication.
-    /// 
+    ///
     /// The default value is *true*.
     pub fn with_auth_sig(mut self, on: bool) -> Self {
         self.cfg.auth_sig = on;
         self
     }
+    /// Add a path pattern requiring login, e.g. "/api/**".
+    ///
+    /// The middleware rejects the unauthenticated requests to these paths with 401,
+    /// so the handlers are protected even without the [WxLoginInfo](crate::core::login::WxLoginInfo)
+    /// extractor. A pattern is a glob, where `*` matches within one path segment and `**` matches
+    /// any number of segments, or a literal path.
+    pub fn with_protected_path(mut self, pattern: &str) -> Self {
+        self.cfg.auth_policy.add_protected(pattern);
+        self
+    }
+    /// Add a path pattern allowing anonymous access, e.g. "/public/**",
+    /// which takes precedence over the protected paths.
+    pub fn with_public_path(mut self, pattern: &str) -> Self {
+        self.cfg.auth_policy.add_public(pattern);
+        self
+    }
+    /// Set whether the paths matching neither protected nor public patterns require login.
+    ///
+    /// The default value is *false*, i.e. the authentication result is only checked by extractors.
+    pub fn with_login_required_by_default(mut self, required: bool) -> Self {
+        self.cfg.auth_policy.set_required_by_default(required);
+        self
+    }
     /// Set the signature valid period.
-    /// 
+    ///
     /// The default value is 600 seconds.
     pub fn with_sig_valid_secs(mut self, secs: u64) -> Self {
         self.cfg.sig_valid_secs = secs;
         self
     }
+    /// Set the allowed clock skew of signatures whose timestamp is ahead of the server.
+    ///
+    /// The default value is 60 seconds.
+    pub fn with_sig_future_skew_secs(mut self, secs: u64) -> Self {
+        self.cfg.sig_future_skew_secs = secs;
+        self
+    }
+    /// Set the accepted signature schemes.
+    ///
+    /// The default value is [SG1, SG2](SigScheme), one can accept only SG2 to require
+    /// the method and body of requests to be signed.
+    pub fn with_sig_schemes(mut self, schemes: &[SigScheme]) -> Self {
+        self.cfg.sig_schemes = schemes.into();
+        self
+    }
+    /// Set the max size of request body buffered for the SG2 signature.
+    ///
+    /// The default value is 2MB.
+    pub fn with_sig_body_limit(mut self, limit: usize) -> Self {
+        self.cfg.sig_body_limit = limit;
+        self
+    }
+    /// Enable or disable the nonce check of signatures, which rejects replayed requests.
+    ///
+    /// The default value is *true*, using a [MemoryNonceStore].
+    pub fn with_nonce_check(mut self, on: bool) -> Self {
+        self.cfg.nonce_store = match on {
+            true => Some(Arc::new(MemoryNonceStore::new())),
+            false => None,
+        };
+        self.cfg.in_memory.nonce_store = on;
+        self
+    }
+    /// Set the store of seen nonces used by the nonce check.
+    ///
+    /// The default value is a [MemoryNonceStore], one can use a shared store for a cluster.
+    pub fn with_nonce_store<T: NonceStore + 'static>(mut self, store: T) -> Self {
+        self.cfg.nonce_store = Some(Arc::new(store));
+        self.cfg.in_memory.nonce_store = TypeId::of::<T>() == TypeId::of::<MemoryNonceStore>();
+        self
+    }
+    /// Set the maximum age of a login session, after which the client must login again.
+    ///
+    /// By default login sessions never expire.
+    pub fn with_session_max_age(mut self, max_age: Duration) -> Self {
+        self.cfg.session_max_age = Some(max_age);
+        self
+    }
+    /// Set the maximum age of a login session which can still be refreshed.
+    ///
+    /// By default it is the same as the session max age, one can set a longer value
+    /// to allow refreshing recently expired sessions.
+    pub fn with_refresh_max_age(mut self, max_age: Duration) -> Self {
+        self.cfg.refresh_max_age = Some(max_age);
+        self
+    }
+    /// Set the store of issued sessions, which allows logging out and revoking sessions.
+    ///
+    /// By default there is no store and stokens are valid until they expire. Note that once a store
+    /// is set, the sessions issued before (or lost by a [MemorySessionStore](crate::core::session::MemorySessionStore)
+    /// on restart) are no longer authenticated.
+    pub fn with_session_store<T: SessionStore + 'static>(mut self, store: T) -> Self {
+        self.cfg.session_store = Some(Arc::new(store));
+        self.cfg.in_memory.session_store = TypeId::of::<T>() == TypeId::of::<MemorySessionStore>();
+        self
+    }
+    /// Set the max number of concurrent sessions of an openid, the oldest sessions are revoked
+    /// when a new login exceeds the limit.
+    ///
+    /// By default there is no limit. It only takes effect with a session store
+    /// (see [with_session_store](Self::with_session_store)).
+    pub fn with_max_sessions_per_openid(mut self, max: usize) -> Self {
+        self.cfg.max_sessions_per_openid = Some(max);
+        self
+    }
+    /// Stop accepting session tokens of the legacy ST1 format after the deadline.
+    ///
+    /// New session tokens are always issued in the ST2 format. By default ST1 tokens are accepted
+    /// until they are older than the session max age, so existing sessions migrate smoothly.
+    /// One can set the deadline to retire them earlier (e.g. when the session max age is not set).
+    pub fn with_st1_accept_until(mut self, deadline: SystemTime) -> Self {
+        self.cfg.st1_accept_until = Some(deadline);
+        self
+    }
+    /// Add a server-side key (with a key id up to 255 bytes) to encrypt session tokens.
+    ///
+    /// By default the key is derived from the app secret, so resetting the app secret logs out
+    /// all users. The first added key is used to encrypt new tokens, while tokens encrypted
+    /// by any added key are accepted until the key is removed from the config.
+    ///
+    /// It panics if the key id is longer than 255 bytes, while the config file and environment variables
+    /// report it as an error instead.
+    pub fn with_token_key(mut self, id: &str, key: &[u8]) -> Self {
+        self.add_token_key(id, key)
+            .expect("token key id is too long");
+        self
+    }
+    /// Add a token key, which fails if the key id is too long (e.g. loaded from a config file).
+    pub(crate) fn add_token_key(&mut self, id: &str, key: &[u8]) -> Result<(), Error> {
+        if id.len() > u8::MAX as usize {
+            return Err(Error::Config(format!(
+                "token key id is longer than 255 bytes: {id}"
+            )));
+        }
+        self.cfg.token_keys.add_key(id, key);
+        Ok(())
+    }
+    /// Keep accepting the session tokens encrypted by the key derived from the app secret
+    /// after token keys are added by [with_token_key](Self::with_token_key).
+    ///
+    /// The default value is *false*, so the tokens issued before adding the first token key
+    /// (including all ST1 tokens) are rejected. One can turn it on during the migration and off to retire the app secret key.
+    pub fn with_app_secret_token_key(mut self, on: bool) -> Self {
+        self.cfg.token_keys.set_app_secret_key(on);
+        self
+    }
+    /// Set the active token key (added by [with_token_key](Self::with_token_key))
+    /// used to encrypt new session tokens.
+    ///
+    /// Panics if the key id is not found.
+    pub fn with_active_token_key(mut self, id: &str) -> Self {
+        self.cfg.token_keys.set_active(id).unwrap();
+        self
+    }
+    /// Set the client used to call WeChat server APIs.
+    ///
+    /// The default value is a [ReqwestWxApiClient] calling the official API server,
+    /// which is configured by the with_api_* options. The options are ignored if a client is set.
+    pub fn with_api_client(mut self, client: impl WxApiClient + 'static) -> Self {
+        self.api_client = Some(Arc::new(client));
+        self
+    }
+    /// Set the base url of WeChat server APIs for the default [ReqwestWxApiClient].
+    ///
+    /// The default value is "https://api.weixin.qq.com", one can point it to a local stand-in server.
+    pub fn with_api_base_url(mut self, base_url: &str) -> Self {
+        self.cfg.api_options.base_url = base_url.into();
+        self
+    }
+    /// Set the connect timeout of WeChat server API calls.
+    ///
+    /// The default value is 5 seconds.
+    pub fn with_api_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.cfg.api_options.connect_timeout = timeout;
+        self
+    }
+    /// Set the total timeout (from connecting to reading the response) of WeChat server API calls.
+    ///
+    /// The default value is 10 seconds.
+    pub fn with_api_timeout(mut self, timeout: Duration) -> Self {
+        self.cfg.api_options.timeout = timeout;
+        self
+    }
+    /// Set the HTTP(S) proxy of WeChat server API calls, e.g. "http://proxy.internal:3128".
+    ///
+    /// By default the proxy is taken from the HTTP_PROXY/HTTPS_PROXY environment variables if any.
+    pub fn with_api_proxy(mut self, url: &str) -> Result<Self, Error> {
+        self.cfg.api_options.set_proxy(url)?;
+        Ok(self)
+    }
+    /// Add trusted root certificates (in PEM format, one or more) for WeChat server API calls,
+    /// e.g. the certificate of a TLS-intercepting egress proxy.
+    pub fn with_api_root_cert(mut self, pem: &[u8]) -> Result<Self, Error> {
+        self.cfg.api_options.add_root_certs(pem)?;
+        Ok(self)
+    }
+    /// Set the alternate base urls of WeChat server APIs, to which the retries are switched in turn,
+    /// e.g. [WX_API_BACKUP_URLS](crate::core::wx_api::WX_API_BACKUP_URLS).
+    ///
+    /// By default there is no failover.
+    pub fn with_api_failover_urls(mut self, urls: &[&str]) -> Self {
+        self.cfg.api_options.failover_urls = urls.iter().map(|v| v.to_string()).collect();
+        self
+    }
+    /// Set the max retries of the retryable WeChat server API errors (connect failures and system busy),
+    /// and the base delay of the jittered exponential backoff.
+    ///
+    /// The default value is 2 retries with 100ms base delay, one can disable retries with 0.
+    pub fn with_api_retry(mut self, max_retries: u32, backoff: Duration) -> Self {
+        self.cfg.api_options.max_retries = max_retries;
+        self.cfg.api_options.retry_backoff = backoff;
+        self
+    }
+    /// Set the circuit breaker of WeChat server APIs, which opens after the consecutive failures
+    /// (connect failures, timeouts and system busy) and fails the logins fast with the code
+    /// *wechat-unavailable* during the open duration.
+    ///
+    /// The default value is 5 failures and 30 seconds, one can disable it with 0 failures.
+    pub fn with_api_circuit_breaker(mut self, failures: u32, open_duration: Duration) -> Self {
+        self.cfg.api_options.breaker_threshold = failures;
+        self.cfg.api_options.breaker_open_duration = open_duration;
+        self
+    }
+    /// Set the max number of concurrent WeChat server API calls (e.g. to stay under the QPS quota),
+    /// the exceeding calls wait for the running ones.
+    ///
+    /// By default there is no limit.
+    pub fn with_api_max_concurrency(mut self, max: usize) -> Self {
+        self.cfg.api_options.max_concurrency = Some(max);
+        self
+    }
+    /// Set the hook called on login.
+    ///
+    /// By default there is no hook.
+    pub fn with_login_hook(mut self, hook: impl LoginHook + 'static) -> Self {
+        self.cfg.login_hook = Some(Arc::new(hook));
+        self
+    }
+    /// Set how long the successful result of a login is reused by the duplicate logins
+    /// with the same (appid, code), while the concurrent ones are always coalesced into one.
+    ///
+    /// The default value is 5 seconds, one can set zero to only coalesce the concurrent logins.
+    pub fn with_login_dedup_ttl(mut self, ttl: Duration) -> Self {
+        self.cfg.login_dedup = Arc::new(LoginDedup::new(ttl));
+        self
+    }
+    /// Set the renderer of error responses.
+    ///
+    /// The default value is [DefaultErrorRenderer].
+    pub fn with_error_renderer(mut self, renderer: impl ErrorRenderer + 'static) -> Self {
+        self.cfg.error_renderer = Arc::new(renderer);
+        self
+    }
+    /// Trust the proxy headers (`X-Forwarded-For` and `X-Real-IP`, or `Forwarded` on actix-web)
+    /// for the client ip recorded by the session store, which should be enabled only behind a reverse proxy
+    /// overwriting these headers, since they are forged easily by clients.
+    ///
+    /// The default value is *false*, i.e. the ip of the peer address is used.
+    pub fn with_trust_proxy_headers(mut self, on: bool) -> Self {
+        self.cfg.trust_proxy_headers = on;
+        self
+    }
+    /// Enable or disable the production mode, which removes the detail of error responses
+    /// to avoid leaking internal errors to clients.
+    ///
+    /// The default value is *false*.
+    pub fn with_production(mut self, on: bool) -> Self {
+        self.cfg.production = on;
+        self
+    }
     /// Build a new Config object using current params.
-    pub fn build(self) -> Config {
+    pub fn build(mut self) -> Config {
+        let app_map = self.cfg.app_map.clone();
+        self.cfg.app_provider = match self.app_provider {
+            Some(provider) if app_map.is_empty() => provider,
+            Some(provider) => Arc::new(StaticFirstAppInfoProvider::new(app_map, provider)),
+            None => app_map,
+        };
+        self.cfg.api_client = Some(match self.api_client {
+            Some(client) => {
+                self.cfg.in_memory.api_client = None;
+                client
+            }
+            None => {
+                let client = ReqwestWxApiClient::with_options(&self.cfg.api_options);
+                self.cfg.in_memory.api_client = Some(client.clone());
+                Arc::new(client)
+            }
+        });
         tracing::info!("use {:?}", self.cfg);
         self.cfg
     }
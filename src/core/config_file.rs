@@ -0,0 +1,577 @@
+use std::{fmt::Display, path::Path, str::FromStr, time::Duration};
+
+use serde::Deserialize;
+
+use crate::core::{
+    config::{AppInfo, ConfigBuilder},
+    security::{Error, SigScheme},
+};
+
+const APP_ENV_PREFIX: &str = "WX_APP_";
+const TOKEN_KEY_ENV_PREFIX: &str = "WX_LOGIN_TOKEN_KEY_";
+const FILE_ENV_SUFFIX: &str = "_FILE";
+
+/// The format of config file, see [ConfigBuilder::with_file].
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ConfigFile {
+    apps: Vec<AppEntry>,
+    login_path: Option<String>,
+    refresh_path: Option<String>,
+    logout_path: Option<String>,
+    auth_sig: Option<bool>,
+    protected_paths: Vec<String>,
+    public_paths: Vec<String>,
+    login_required_by_default: Option<bool>,
+    sig_valid_secs: Option<u64>,
+    sig_future_skew_secs: Option<u64>,
+    sig_schemes: Option<Vec<SigScheme>>,
+    sig_body_limit: Option<usize>,
+    nonce_check: Option<bool>,
+    session_max_age_secs: Option<u64>,
+    refresh_max_age_secs: Option<u64>,
+    max_sessions_per_openid: Option<usize>,
+    login_dedup_ttl_secs: Option<u64>,
+    st1_accept_until: Option<u64>,
+    token_keys: Vec<TokenKeyEntry>,
+    active_token_key: Option<String>,
+    app_secret_token_key: Option<bool>,
+    api_base_url: Option<String>,
+    api_connect_timeout_secs: Option<u64>,
+    api_timeout_secs: Option<u64>,
+    api_proxy: Option<String>,
+    api_root_cert_files: Vec<String>,
+    api_failover_urls: Option<Vec<String>>,
+    api_max_retries: Option<u32>,
+    api_retry_backoff_ms: Option<u64>,
+    api_breaker_failures: Option<u32>,
+    api_breaker_open_secs: Option<u64>,
+    api_max_concurrency: Option<usize>,
+    trust_proxy_headers: Option<bool>,
+    production: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct AppEntry {
+    appid: String,
+    secret: Option<String>,
+    secret_file: Option<String>,
+    fallback_secret: Option<String>,
+    fallback_secret_file: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TokenKeyEntry {
+    id: String,
+    key: Option<String>,
+    key_file: Option<String>,
+}
+
+impl ConfigFile {
+    /// Load the config file, which is parsed as JSON if the extension is .json, otherwise TOML.
+    pub(crate) fn load(path: &Path) -> Result<Self, Error> {
+        let text = read_file(path)?;
+        let res = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&text).map_err(|e| e.to_string()),
+            _ => toml::from_str(&text).map_err(|e| e.to_string()),
+        };
+        res.map_err(|e| Error::Config(format!("parse config file {path:?} fail: {e}")))
+    }
+
+    /// Apply the values of the config file to the builder.
+    pub(crate) fn apply(self, mut builder: ConfigBuilder) -> Result<ConfigBuilder, Error> {
+        for app in self.apps {
+            let secret = value_or_file(app.secret, app.secret_file)?
+                .ok_or_else(|| Error::Config(format!("no secret of app {}", app.appid)))?;
+            let mut app_info = AppInfo::from(app.appid, secret);
+            if let Some(secret) = value_or_file(app.fallback_secret, app.fallback_secret_file)? {
+                app_info = app_info.with_fallback_secret(secret);
+            }
+            builder = builder.with_app_info(app_info);
+        }
+        for token_key in self.token_keys {
+            let key = value_or_file(token_key.key, token_key.key_file)?
+                .ok_or_else(|| Error::Config(format!("no key of token key {}", token_key.id)))?;
+            builder.add_token_key(&token_key.id, key.as_bytes())?;
+        }
+        if let Some(v) = self.active_token_key {
+            builder.cfg.token_keys.set_active(&v)?;
+        }
+        if let Some(v) = self.app_secret_token_key {
+            builder = builder.with_app_secret_token_key(v);
+        }
+        if let Some(v) = self.login_path {
+            builder = builder.with_login_path(&v);
+        }
+        if let Some(v) = self.refresh_path {
+            builder = builder.with_refresh_path(&v);
+        }
+        if let Some(v) = self.logout_path {
+            builder = builder.with_logout_path(&v);
+        }
+        if let Some(v) = self.auth_sig {
+            builder = builder.with_auth_sig(v);
+        }
+        for v in self.protected_paths {
+            builder = builder.with_protected_path(&v);
+        }
+        for v in self.public_paths {
+            builder = builder.with_public_path(&v);
+        }
+        if let Some(v) = self.login_required_by_default {
+            builder = builder.with_login_required_by_default(v);
+        }
+        if let Some(v) = self.sig_valid_secs {
+            builder = builder.with_sig_valid_secs(v);
+        }
+        if let Some(v) = self.sig_future_skew_secs {
+            builder = builder.with_sig_future_skew_secs(v);
+        }
+        if let Some(v) = self.sig_schemes {
+            builder = builder.with_sig_schemes(&v);
+        }
+        if let Some(v) = self.sig_body_limit {
+            builder = builder.with_sig_body_limit(v);
+        }
+        if let Some(v) = self.nonce_check {
+            builder = builder.with_nonce_check(v);
+        }
+        if let Some(v) = self.session_max_age_secs {
+            builder = builder.with_session_max_age(Duration::from_secs(v));
+        }
+        if let Some(v) = self.refresh_max_age_secs {
+            builder = builder.with_refresh_max_age(Duration::from_secs(v));
+        }
+        if let Some(v) = self.max_sessions_per_openid {
+            builder = builder.with_max_sessions_per_openid(v);
+        }
+        if let Some(v) = self.login_dedup_ttl_secs {
+            builder = builder.with_login_dedup_ttl(Duration::from_secs(v));
+        }
+        if let Some(v) = self.st1_accept_until {
+            builder = builder.with_st1_accept_until(std::time::UNIX_EPOCH + Duration::from_secs(v));
+        }
+        if let Some(v) = self.api_base_url {
+            builder = builder.with_api_base_url(&v);
+        }
+        if let Some(v) = self.api_connect_timeout_secs {
+            builder = builder.with_api_connect_timeout(Duration::from_secs(v));
+        }
+        if let Some(v) = self.api_timeout_secs {
+            builder = builder.with_api_timeout(Duration::from_secs(v));
+        }
+        if let Some(v) = self.api_proxy {
+            builder = builder.with_api_proxy(&v)?;
+        }
+        for file in self.api_root_cert_files {
+            builder = builder.with_api_root_cert(read_file(file)?.as_bytes())?;
+        }
+        if let Some(v) = self.api_failover_urls {
+            builder =
+                builder.with_api_failover_urls(&v.iter().map(String::as_str).collect::<Vec<_>>());
+        }
+        if self.api_max_retries.is_some() || self.api_retry_backoff_ms.is_some() {
+            let options = &builder.cfg.api_options;
+            let max_retries = self.api_max_retries.unwrap_or(options.max_retries);
+            let backoff = self
+                .api_retry_backoff_ms
+                .map_or(options.retry_backoff, Duration::from_millis);
+            builder = builder.with_api_retry(max_retries, backoff);
+        }
+        if self.api_breaker_failures.is_some() || self.api_breaker_open_secs.is_some() {
+            let options = &builder.cfg.api_options;
+            let failures = self
+                .api_breaker_failures
+                .unwrap_or(options.breaker_threshold);
+            let open_duration = self
+                .api_breaker_open_secs
+                .map_or(options.breaker_open_duration, Duration::from_secs);
+            builder = builder.with_api_circuit_breaker(failures, open_duration);
+        }
+        if let Some(v) = self.api_max_concurrency {
+            builder = builder.with_api_max_concurrency(v);
+        }
+        if let Some(v) = self.trust_proxy_headers {
+            builder = builder.with_trust_proxy_headers(v);
+        }
+        if let Some(v) = self.production {
+            builder = builder.with_production(v);
+        }
+        Ok(builder)
+    }
+}
+
+/// Apply the values of environment variables to the builder, see [ConfigBuilder::with_env_var].
+///
+/// If not strict, the invalid variables are logged and skipped instead of failing.
+pub(crate) fn apply_env(mut builder: ConfigBuilder, strict: bool) -> Result<ConfigBuilder, Error> {
+    let env = EnvReader { strict };
+    for (appid, secret) in env.prefixed(APP_ENV_PREFIX)? {
+        builder = builder.with_app_info(AppInfo::from(appid, secret));
+    }
+    let token_keys = env.prefixed(TOKEN_KEY_ENV_PREFIX)?;
+    if !token_keys.is_empty() {
+        builder.cfg.token_keys.clear_keys();
+    }
+    for (id, key) in token_keys {
+        env.check(builder.add_token_key(&id, key.as_bytes()))?;
+    }
+    if let Some(v) = env.value("WX_LOGIN_ACTIVE_TOKEN_KEY")? {
+        env.check(builder.cfg.token_keys.set_active(&v))?;
+    }
+    if let Some(v) = env.parse("WX_LOGIN_APP_SECRET_TOKEN_KEY")? {
+        builder = builder.with_app_secret_token_key(v);
+    }
+    if let Some(v) = env.value("WX_LOGIN_LOGIN_PATH")? {
+        builder = builder.with_login_path(&v);
+    }
+    if let Some(v) = env.value("WX_LOGIN_REFRESH_PATH")? {
+        builder = builder.with_refresh_path(&v);
+    }
+    if let Some(v) = env.value("WX_LOGIN_LOGOUT_PATH")? {
+        builder = builder.with_logout_path(&v);
+    }
+    if let Some(v) = env.parse("WX_LOGIN_AUTH_SIG")? {
+        builder = builder.with_auth_sig(v);
+    }
+    if let Some(v) = env.value("WX_LOGIN_PROTECTED_PATHS")? {
+        builder.cfg.auth_policy.clear_protected();
+        for pattern in v.split(',') {
+            builder = builder.with_protected_path(pattern.trim());
+        }
+    }
+    if let Some(v) = env.value("WX_LOGIN_PUBLIC_PATHS")? {
+        builder.cfg.auth_policy.clear_public();
+        for pattern in v.split(',') {
+            builder = builder.with_public_path(pattern.trim());
+        }
+    }
+    if let Some(v) = env.parse("WX_LOGIN_LOGIN_REQUIRED_BY_DEFAULT")? {
+        builder = builder.with_login_required_by_default(v);
+    }
+    if let Some(v) = env.parse("WX_LOGIN_SIG_VALID_SECS")? {
+        builder = builder.with_sig_valid_secs(v);
+    }
+    if let Some(v) = env.parse("WX_LOGIN_SIG_FUTURE_SKEW_SECS")? {
+        builder = builder.with_sig_future_skew_secs(v);
+    }
+    if let Some(v) = env.value("WX_LOGIN_SIG_SCHEMES")? {
+        let schemes = v
+            .split(',')
+            .map(|tag| {
+                SigScheme::from_tag(tag.trim())
+                    .ok_or_else(|| Error::Config(format!("bad sig scheme: {tag}")))
+            })
+            .collect::<Result<Vec<_>, _>>();
+        if let Some(schemes) = env.check(schemes)? {
+            builder = builder.with_sig_schemes(&schemes);
+        }
+    }
+    if let Some(v) = env.parse("WX_LOGIN_SIG_BODY_LIMIT")? {
+        builder = builder.with_sig_body_limit(v);
+    }
+    if let Some(v) = env.parse("WX_LOGIN_NONCE_CHECK")? {
+        builder = builder.with_nonce_check(v);
+    }
+    if let Some(v) = env.parse("WX_LOGIN_SESSION_MAX_AGE_SECS")? {
+        builder = builder.with_session_max_age(Duration::from_secs(v));
+    }
+    if let Some(v) = env.parse("WX_LOGIN_REFRESH_MAX_AGE_SECS")? {
+        builder = builder.with_refresh_max_age(Duration::from_secs(v));
+    }
+    if let Some(v) = env.parse("WX_LOGIN_MAX_SESSIONS_PER_OPENID")? {
+        builder = builder.with_max_sessions_per_openid(v);
+    }
+    if let Some(v) = env.parse("WX_LOGIN_LOGIN_DEDUP_TTL_SECS")? {
+        builder = builder.with_login_dedup_ttl(Duration::from_secs(v));
+    }
+    if let Some(v) = env.parse("WX_LOGIN_ST1_ACCEPT_UNTIL")? {
+        builder = builder.with_st1_accept_until(std::time::UNIX_EPOCH + Duration::from_secs(v));
+    }
+    if let Some(v) = env.value("WX_LOGIN_API_BASE_URL")? {
+        builder = builder.with_api_base_url(&v);
+    }
+    if let Some(v) = env.parse("WX_LOGIN_API_CONNECT_TIMEOUT_SECS")? {
+        builder = builder.with_api_connect_timeout(Duration::from_secs(v));
+    }
+    if let Some(v) = env.parse("WX_LOGIN_API_TIMEOUT_SECS")? {
+        builder = builder.with_api_timeout(Duration::from_secs(v));
+    }
+    if let Some(v) = env.value("WX_LOGIN_API_PROXY")? {
+        env.check(builder.cfg.api_options.set_proxy(&v))?;
+    }
+    // the PEM content, or WX_LOGIN_API_ROOT_CERT_FILE for the path of PEM file
+    if let Some(v) = env.value("WX_LOGIN_API_ROOT_CERT")? {
+        env.check(builder.cfg.api_options.add_root_certs(v.as_bytes()))?;
+    }
+    if let Some(v) = env.value("WX_LOGIN_API_FAILOVER_URLS")? {
+        builder = builder.with_api_failover_urls(&v.split(',').map(str::trim).collect::<Vec<_>>());
+    }
+    let max_retries = env.parse("WX_LOGIN_API_MAX_RETRIES")?;
+    let backoff_ms = env.parse("WX_LOGIN_API_RETRY_BACKOFF_MS")?;
+    if max_retries.is_some() || backoff_ms.is_some() {
+        let options = &builder.cfg.api_options;
+        let max_retries = max_retries.unwrap_or(options.max_retries);
+        let backoff = backoff_ms.map_or(options.retry_backoff, Duration::from_millis);
+        builder = builder.with_api_retry(max_retries, backoff);
+    }
+    let breaker_failures = env.parse("WX_LOGIN_API_BREAKER_FAILURES")?;
+    let breaker_open_secs = env.parse("WX_LOGIN_API_BREAKER_OPEN_SECS")?;
+    if breaker_failures.is_some() || breaker_open_secs.is_some() {
+        let options = &builder.cfg.api_options;
+        let failures = breaker_failures.unwrap_or(options.breaker_threshold);
+        let open_duration =
+            breaker_open_secs.map_or(options.breaker_open_duration, Duration::from_secs);
+        builder = builder.with_api_circuit_breaker(failures, open_duration);
+    }
+    if let Some(v) = env.parse("WX_LOGIN_API_MAX_CONCURRENCY")? {
+        builder = builder.with_api_max_concurrency(v);
+    }
+    if let Some(v) = env.parse("WX_LOGIN_TRUST_PROXY_HEADERS")? {
+        builder = builder.with_trust_proxy_headers(v);
+    }
+    if let Some(v) = env.parse("WX_LOGIN_PRODUCTION")? {
+        builder = builder.with_production(v);
+    }
+    Ok(builder)
+}
+
+/// The reader of environment variables, which skips the invalid ones if not strict.
+struct EnvReader {
+    strict: bool,
+}
+
+impl EnvReader {
+    /// Return the error if strict, otherwise log it and return None.
+    fn check<T>(&self, res: Result<T, Error>) -> Result<Option<T>, Error> {
+        match res {
+            Ok(v) => Ok(Some(v)),
+            Err(e) if self.strict => Err(e),
+            Err(e) => {
+                tracing::error!("skip invalid env var: {e}");
+                Ok(None)
+            }
+        }
+    }
+
+    fn value(&self, name: &str) -> Result<Option<String>, Error> {
+        self.check(env_value(name)).map(Option::flatten)
+    }
+
+    fn parse<T: FromStr>(&self, name: &str) -> Result<Option<T>, Error>
+    where
+        T::Err: Display,
+    {
+        self.check(env_parse(name)).map(Option::flatten)
+    }
+
+    fn prefixed(&self, prefix: &str) -> Result<Vec<(String, String)>, Error> {
+        let values = prefixed_env_values(prefix).into_iter();
+        Ok(values
+            .map(|v| self.check(v))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+}
+
+fn read_file(path: impl AsRef<Path>) -> Result<String, Error> {
+    let path = path.as_ref();
+    std::fs::read_to_string(path)
+        .map_err(|e| Error::Config(format!("read file {path:?} fail: {e}")))
+}
+
+/// Read a secret file, ignoring the trailing newline.
+fn read_secret_file(path: impl AsRef<Path>) -> Result<String, Error> {
+    Ok(read_file(path)?.trim_end_matches(['\r', '\n']).into())
+}
+
+fn value_or_file(value: Option<String>, file: Option<String>) -> Result<Option<String>, Error> {
+    match (value, file) {
+        (Some(value), _) => Ok(Some(value)),
+        (None, Some(file)) => read_secret_file(file).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Get the value of NAME, or the content of the file specified by NAME_FILE.
+fn env_value(name: &str) -> Result<Option<String>, Error> {
+    if let Ok(value) = std::env::var(name) {
+        return Ok(Some(value));
+    }
+    match std::env::var(name.to_string() + FILE_ENV_SUFFIX) {
+        Ok(file) => read_secret_file(file).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+fn env_parse<T: FromStr>(name: &str) -> Result<Option<T>, Error>
+where
+    T::Err: Display,
+{
+    env_value(name)?
+        .map(|v| {
+            v.parse()
+                .map_err(|e| Error::Config(format!("bad value of {name}: {e}")))
+        })
+        .transpose()
+}
+
+/// Get (key, value) of all PREFIX<key> and PREFIX<key>_FILE variables.
+fn prefixed_env_values(prefix: &str) -> Vec<Result<(String, String), Error>> {
+    let mut values = vec![];
+    for (name, value) in std::env::vars().filter(|(k, _)| k.starts_with(prefix)) {
+        let key = &name[prefix.len()..];
+        match key.strip_suffix(FILE_ENV_SUFFIX) {
+            // the plain value takes precedence over the file
+            Some(key) if std::env::var(prefix.to_string() + key).is_err() => {
+                values.push(read_secret_file(value).map(|v| (key.into(), v)))
+            }
+            Some(_) => (),
+            None => values.push(Ok((key.into(), value))),
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// The lock of the tests changing the process-wide environment variables.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn load_toml_config_file() {
+        let dir = std::env::temp_dir().join(format!("wx-login-test-{}", fastrand::u64(..)));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("secret"), "file_secret\n").unwrap();
+        let toml_path = dir.join("wx_login.toml");
+        std::fs::write(
+            &toml_path,
+            format!(
+                r#"
+                login_path = "/api/login"
+                auth_sig = false
+                sig_schemes = ["SG2"]
+                session_max_age_secs = 3600
+                api_timeout_secs = 3
+                api_proxy = "http://127.0.0.1:3128"
+                trust_proxy_headers = true
+
+                [[apps]]
+                appid = "app1"
+                secret = "secret1"
+
+                [[apps]]
+                appid = "app2"
+                secret_file = "{}"
+                "#,
+                dir.join("secret").display()
+            ),
+        )
+        .unwrap();
+        let cfg = ConfigFile::load(&toml_path)
+            .unwrap()
+            .apply(ConfigBuilder::new())
+            .unwrap()
+            .build();
+        assert_eq!(cfg.login_path, "/api/login");
+        assert!(!cfg.auth_sig);
+        assert_eq!(cfg.sig_schemes, [SigScheme::SG2]);
+        assert_eq!(cfg.session_max_age, Some(Duration::from_secs(3600)));
+        assert_eq!(cfg.app_map["app1"].secret.0, "secret1");
+        assert_eq!(cfg.app_map["app2"].secret.0, "file_secret");
+        assert_eq!(cfg.api_options.timeout, Duration::from_secs(3));
+        assert!(cfg.api_options.proxy.is_some());
+        assert!(cfg.trust_proxy_headers);
+        let json_path = dir.join("wx_login.json");
+        std::fs::write(&json_path, r#"{"apps": [{"appid": "app3"}]}"#).unwrap();
+        assert!(ConfigFile::load(&json_path)
+            .unwrap()
+            .apply(ConfigBuilder::new())
+            .is_err());
+        let json = format!(
+            r#"{{"api_root_cert_files": ["{}"]}}"#,
+            dir.join("secret").display()
+        );
+        std::fs::write(&json_path, json).unwrap();
+        assert!(ConfigFile::load(&json_path)
+            .unwrap()
+            .apply(ConfigBuilder::new())
+            .is_err());
+        let json = format!(
+            r#"{{"token_keys": [{{"id": "{}", "key": "k"}}]}}"#,
+            "k".repeat(256)
+        );
+        std::fs::write(&json_path, json).unwrap();
+        let err = ConfigFile::load(&json_path)
+            .unwrap()
+            .apply(ConfigBuilder::new())
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), "config-invalid");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_env_vars() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let dir = std::env::temp_dir().join(format!("wx-login-test-{}", fastrand::u64(..)));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("secret"), "file_secret\n").unwrap();
+        let file_builder = || {
+            let file: ConfigFile = toml::from_str(
+                r#"
+                protected_paths = ["/a/**"]
+                sig_valid_secs = 300
+
+                [[token_keys]]
+                id = "k1"
+                key = "some_token_key_1"
+                "#,
+            )
+            .unwrap();
+            file.apply(ConfigBuilder::new()).unwrap()
+        };
+        let long_key_var = format!("WX_LOGIN_TOKEN_KEY_{}", "k".repeat(256));
+        let vars = [
+            ("WX_APP_app9_FILE", dir.join("secret").display().to_string()),
+            ("WX_LOGIN_TOKEN_KEY_k2", "some_token_key_2".into()),
+            (&long_key_var, "some_token_key_3".into()),
+            ("WX_LOGIN_PROTECTED_PATHS", "/b/**, /c".into()),
+            ("WX_LOGIN_SESSION_MAX_AGE_SECS", "60".into()),
+            ("WX_LOGIN_SIG_VALID_SECS", "bad".into()),
+            (
+                "WX_LOGIN_LOGIN_PATH_FILE",
+                dir.join("missing").display().to_string(),
+            ),
+        ];
+        for (name, value) in &vars {
+            std::env::set_var(name, value);
+        }
+        let err = file_builder().try_with_env_var().err().unwrap();
+        // the invalid variables are skipped by with_env_var
+        let cfg = file_builder().with_env_var().build();
+        for (name, _) in &vars {
+            std::env::remove_var(name);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(err.code(), "config-invalid");
+        assert_eq!(cfg.app_map["app9"].secret.0, "file_secret");
+        assert_eq!(cfg.session_max_age, Some(Duration::from_secs(60)));
+        assert_eq!(cfg.sig_valid_secs, 300);
+        assert_eq!(cfg.login_path, "/login");
+        // the list values replace the ones of the config file
+        assert!(cfg.auth_policy.requires_login("/b/x"));
+        assert!(cfg.auth_policy.requires_login("/c"));
+        assert!(!cfg.auth_policy.requires_login("/a/x"));
+        let token_keys = format!("{:?}", cfg.token_keys);
+        assert!(token_keys.contains("\"k2\"") && !token_keys.contains("\"k1\""));
+        assert!(!token_keys.contains("kkk"));
+    }
+}
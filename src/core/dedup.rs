@@ -0,0 +1,144 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::OnceCell;
+
+use crate::core::login::{WxLoginErr, WxLoginOk};
+
+type LoginResult = Result<WxLoginOk, WxLoginErr>;
+type LoginCell = Arc<OnceCell<(LoginResult, Instant)>>;
+
+const MIN_PRUNE_LEN: usize = 1024;
+
+/// Coalesces the concurrent logins with the same (appid, code) into one,
+/// and caches the successful results for a short time.
+///
+/// A login code is single-use, so the duplicate logins (e.g. fired twice by a page on cold start)
+/// would otherwise fail with "code been used".
+#[derive(Debug)]
+pub(crate) struct LoginDedup {
+    ttl: Duration,
+    inner: Mutex<LoginCells>,
+}
+
+#[derive(Debug, Default)]
+struct LoginCells {
+    map: HashMap<(String, String), LoginCell>,
+    prune_len: usize,
+}
+
+/// Whether the cell is done with an error or its result is older than the ttl.
+fn is_stale(cell: &LoginCell, ttl: Duration, now: Instant) -> bool {
+    cell.get()
+        .is_some_and(|(res, done)| res.is_err() || *done + ttl <= now)
+}
+
+impl LoginDedup {
+    /// Create a LoginDedup caching the successful results for the ttl,
+    /// only the concurrent logins are coalesced if the ttl is zero.
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            inner: Default::default(),
+        }
+    }
+
+    pub(crate) fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Run the login of (appid, code), or wait for the result of the running one.
+    pub(crate) async fn run<F, Fut>(&self, appid: &str, code: &str, login: F) -> LoginResult
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = LoginResult>,
+    {
+        let key = (appid.to_string(), code.to_string());
+        let cell = {
+            let now = Instant::now();
+            let mut inner = self.inner.lock().unwrap();
+            if inner.map.len() >= inner.prune_len {
+                inner.map.retain(|_, v| !is_stale(v, self.ttl, now));
+                inner.prune_len = (inner.map.len() * 2).max(MIN_PRUNE_LEN);
+            }
+            match inner.map.get(&key) {
+                Some(cell) if !is_stale(cell, self.ttl, now) => cell.clone(),
+                _ => {
+                    let cell = LoginCell::default();
+                    inner.map.insert(key.clone(), cell.clone());
+                    cell
+                }
+            }
+        };
+        // if the running login is cancelled, one of the waiters takes over
+        let (res, _) = cell
+            .get_or_init(|| async { (login().await, Instant::now()) })
+            .await;
+        if res.is_err() || self.ttl.is_zero() {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.map.get(&key).is_some_and(|v| Arc::ptr_eq(v, &cell)) {
+                inner.map.remove(&key);
+            }
+        }
+        res.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn login_ok(stoken: &str) -> WxLoginOk {
+        WxLoginOk {
+            openid: "some-openid".into(),
+            unionid: None,
+            stoken: stoken.into(),
+            skey: "some-skey".into(),
+            expires_at: None,
+            extra: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn coalesce_concurrent_logins() {
+        let dedup = LoginDedup::new(Duration::from_secs(5));
+        let calls = AtomicUsize::new(0);
+        let login = |code: &'static str| {
+            let (dedup, calls) = (&dedup, &calls);
+            async move {
+                dedup
+                    .run("app", code, || async {
+                        let n = calls.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        match code {
+                            "bad" => Err(WxLoginErr {
+                                status: 401,
+                                code: "wx-code-invalid".into(),
+                                message: "".into(),
+                                detail: "".into(),
+                                server_time: None,
+                            }),
+                            _ => Ok(login_ok(&n.to_string())),
+                        }
+                    })
+                    .await
+            }
+        };
+        let (res1, res2) = tokio::join!(login("c1"), login("c1"));
+        assert_eq!(res1.unwrap().stoken, res2.unwrap().stoken);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        // the successful result is cached
+        assert_eq!(login("c1").await.unwrap().stoken, "0");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(login("c2").await.unwrap().stoken, "1");
+        // the errors are not cached
+        assert!(login("bad").await.is_err());
+        assert!(login("bad").await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+}
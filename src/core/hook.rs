@@ -0,0 +1,26 @@
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+
+use crate::core::login::WxLoginErr;
+
+/// Extra fields merged into the login response by [LoginHook].
+pub type LoginExtra = serde_json::Map<String, serde_json::Value>;
+
+/// The user info of a login passed to [LoginHook].
+#[derive(Debug, Clone)]
+pub struct LoginContext {
+    pub appid: String,
+    pub openid: String,
+    pub unionid: Option<String>,
+}
+
+/// A hook called on login, which can be used for user provisioning or banning.
+#[async_trait]
+pub trait LoginHook: Send + Sync + Debug {
+    /// Called after code2session succeeds and before the session token is made.
+    ///
+    /// Return extra fields (e.g. the internal user id) to merge into the login response,
+    /// or a [WxLoginErr] to reject the login.
+    async fn on_login(&self, ctx: &LoginContext) -> Result<LoginExtra, WxLoginErr>;
+}
@@ -1,8 +1,12 @@
-use crate::core::config::Config;
-use crate::core::security::Authority;
+use crate::core::config::{AppInfo, Config};
+use crate::core::hook::{LoginContext, LoginExtra};
+use crate::core::reload::ConfigHandle;
+use crate::core::security::{Authority, SigRequest, SigScheme, TokenFormat};
+use crate::core::session::{session_expires_at, ClientInfo, SessionRecord, SessionStore};
+use crate::core::wx_api::WxApiError;
 use itertools::Itertools;
-use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{fmt::Display, sync::Arc};
 use tiny_crypto::encoding::{Encoder, BASE64};
 
@@ -12,18 +16,25 @@ pub use crate::core::security::Error;
 pub use crate::core::security::ServerSession as Secret;
 
 pub(crate) const LOGIN_FAIL_MSG: &str = "登录验证失败";
-#[allow(dead_code)]
 pub(crate) const AUTH_FAIL_MSG: &str = "登录会话验证失败";
-pub(crate) const WX_JSCODE2SESSION_URL: &str = "https://api.weixin.qq.com/sns/jscode2session";
 
 /// The login ok result.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 pub struct WxLoginOk {
     pub openid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unionid: Option<String>,
     pub stoken: String,
     pub skey: String,
+    /// The unix timestamp (in seconds) when the session expires.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    #[serde(flatten)]
+    pub extra: LoginExtra,
 }
 
+const LOGIN_OK_FIELDS: &[&str] = &["openid", "unionid", "stoken", "skey", "expires_at"];
+
 /// The login fail result.
 #[derive(Serialize, Debug, Clone)]
 pub struct WxLoginErr {
@@ -31,6 +42,10 @@ pub struct WxLoginErr {
     pub code: String,
     pub message: String,
     pub detail: String,
+    /// The server unix time (in milliseconds) returned with authentication errors,
+    /// which can be used by client to compute its clock offset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_time: Option<u64>,
 }
 
 /// The inner struct of [WxLoginInfo].
@@ -38,6 +53,7 @@ pub struct WxLoginErr {
 pub struct WxLoginInfoInner {
     pub appid: String,
     pub openid: String,
+    pub unionid: Option<String>,
     pub secret: Secret,
     pub sig_authed: bool,
 }
@@ -60,96 +76,637 @@ impl std::ops::Deref for WxLoginInfo {
 /// The core struct for login and authentication process.
 #[derive(Debug, Clone)]
 pub struct WxLogin {
-    pub cfg: Arc<Config>,
+    pub cfg: ConfigHandle,
 }
 
 impl WxLogin {
-    /// Create a new WxLogin with Config.
-    pub fn new(cfg: Arc<Config>) -> Self {
-        Self { cfg }
+    /// Create a new WxLogin with Config or a reloadable [ConfigHandle].
+    pub fn new(cfg: impl Into<ConfigHandle>) -> Self {
+        Self { cfg: cfg.into() }
     }
 
     /// Handle login request.
-    #[tracing::instrument(err(Debug), ret, skip_all)]
     pub async fn handle_login(&self, appid: String, code: String) -> Result<WxLoginOk, WxLoginErr> {
+        self.handle_login_with_client(appid, code, ClientInfo::default())
+            .await
+    }
+
+    /// Handle login request from the client, whose info is recorded by the session store.
+    ///
+    /// The concurrent logins with the same (appid, code) are coalesced into one WeChat call,
+    /// and the successful result is reused for a short time (see
+    /// [with_login_dedup_ttl](crate::core::config::ConfigBuilder::with_login_dedup_ttl)).
+    #[tracing::instrument(err(Debug), ret, skip_all)]
+    pub async fn handle_login_with_client(
+        &self,
+        appid: String,
+        code: String,
+        client: ClientInfo,
+    ) -> Result<WxLoginOk, WxLoginErr> {
+        let cfg = self.cfg.load();
+        cfg.login_dedup
+            .run(&appid, &code, || self.login(&cfg, &appid, &code, client))
+            .await
+    }
+
+    async fn login(
+        &self,
+        cfg: &Config,
+        appid: &str,
+        code: &str,
+        client: ClientInfo,
+    ) -> Result<WxLoginOk, WxLoginErr> {
         tracing::info!("start handle_login({appid}, {code})");
-        let app_info = self.cfg.app_map.get(&appid).ok_or(WxLoginErr {
-            status: 401,
-            code: "appid-not-found".into(),
-            message: LOGIN_FAIL_MSG.into(),
-            detail: "".into(),
-        })?;
-        let client = reqwest::Client::new();
-        let url = WX_JSCODE2SESSION_URL;
-        let code2sess_req =
-            proto::Code2SessionRequest::from(appid.clone(), app_info.secret.0.clone(), code);
-        let res = client
-            .get(url)
-            .query(&code2sess_req)
-            .send()
-            .await
-            .map_err(err_resp(500, "jscode2session-call-fail"))?;
-        let code2sess_res = res
-            .json::<proto::Code2SessionResponse>()
-            .await
-            .map_err(err_resp(401, "jscode2session-resp-fail"))?;
+        let app_info = cfg
+            .get_app_info(appid)
+            .await
+            .map_err(err_resp(500, "app-info-provider-fail"))?
+            .ok_or(WxLoginErr {
+                status: 401,
+                code: "appid-not-found".into(),
+                message: LOGIN_FAIL_MSG.into(),
+                detail: "".into(),
+                server_time: None,
+            })?;
+        let api_client = cfg.api_client();
+        let mut code2sess_res = api_client
+            .code2session(appid, &app_info.secret.0, code)
+            .await;
+        if let (Err(WxApiError::WeChat { errcode: 40125, .. }), Some(fallback_secret)) =
+            (&code2sess_res, &app_info.fallback_secret)
+        {
+            code2sess_res = api_client
+                .code2session(appid, &fallback_secret.0, code)
+                .await;
+            tracing::warn!(
+                event = "wx_login.fallback_secret_used",
+                appid,
+                ok = code2sess_res.is_ok(),
+                "retry code2session with the fallback secret"
+            );
+        }
+        let code2sess_res = code2sess_res.map_err(api_err_resp)?;
         tracing::info!(?code2sess_res);
         let openid = code2sess_res.openid;
+        let unionid = code2sess_res.unionid;
+        let mut extra = LoginExtra::new();
+        if let Some(hook) = &cfg.login_hook {
+            let ctx = LoginContext {
+                appid: appid.into(),
+                openid: openid.clone(),
+                unionid: unionid.clone(),
+            };
+            extra = hook.on_login(&ctx).await?;
+            extra.retain(|k, _| {
+                let reserved = LOGIN_OK_FIELDS.contains(&k.as_str());
+                if reserved {
+                    tracing::warn!("ignore reserved field {k} from login hook");
+                }
+                !reserved
+            });
+        }
         let session_key: [u8; 16] = BASE64
             .from_text(&code2sess_res.session_key)
             .map_err(err_resp(500, "session-key-invalid-base64"))?
             .try_into()
             .map_err(|v: Vec<u8>| format!("unexpected key len: {}", v.len()))
             .map_err(err_resp(500, "session-key-invalid-base64"))?;
-        let authority = Authority::new(app_info);
-        let client_sess = authority.make_client_session(&openid, &session_key);
-        Ok(WxLoginOk {
+        self.make_login_ok(
+            cfg,
+            &app_info,
+            openid,
+            unionid,
+            &session_key,
+            extra,
+            client,
+            None,
+        )
+        .await
+    }
+
+    /// Handle refresh request, which reissues stoken and skey of a valid session
+    /// without calling wx.login again.
+    pub async fn handle_refresh(
+        &self,
+        stoken: &str,
+        req: &SigRequest<'_>,
+        sig: Result<&str, Error>,
+    ) -> Result<WxLoginOk, WxLoginErr> {
+        self.handle_refresh_with_client(stoken, req, sig, ClientInfo::default())
+            .await
+    }
+
+    /// Handle refresh request from the client, whose info is recorded by the session store.
+    pub async fn handle_refresh_with_client(
+        &self,
+        stoken: &str,
+        req: &SigRequest<'_>,
+        sig: Result<&str, Error>,
+        client: ClientInfo,
+    ) -> Result<WxLoginOk, WxLoginErr> {
+        self.handle_refresh_with_auth(None, stoken, req, sig, client)
+            .await
+    }
+
+    /// Handle refresh request, reusing the result of the middleware if the request is authenticated by it.
+    #[tracing::instrument(err(Debug), ret, skip_all)]
+    pub(crate) async fn handle_refresh_with_auth(
+        &self,
+        auth_res: Option<WxLoginAuthResult>,
+        stoken: &str,
+        req: &SigRequest<'_>,
+        sig: Result<&str, Error>,
+        client: ClientInfo,
+    ) -> Result<WxLoginOk, WxLoginErr> {
+        let cfg = self.cfg.load();
+        let max_age = cfg.refresh_max_age.or(cfg.session_max_age);
+        let login_info = self
+            .reuse_or_authenticate(&cfg, auth_res, stoken, req, sig, max_age)
+            .await
+            .map_err(auth_err_resp)?;
+        let app_info = cfg
+            .get_app_info(&login_info.appid)
+            .await
+            .and_then(|v| v.ok_or_else(|| Error::AppNotFound(login_info.appid.clone())))
+            .map_err(auth_err_resp)?;
+        // the refreshed session replaces the old one
+        self.make_login_ok(
+            &cfg,
+            &app_info,
+            login_info.openid.clone(),
+            login_info.unionid.clone(),
+            &login_info.secret.session_key,
+            LoginExtra::new(),
+            client,
+            Some(&login_info.secret.session_id),
+        )
+        .await
+    }
+
+    /// Handle logout request, which revokes the session in the session store.
+    ///
+    /// Without a session store the stoken stays valid until it expires,
+    /// so the client should discard it anyway.
+    pub async fn handle_logout(
+        &self,
+        stoken: &str,
+        req: &SigRequest<'_>,
+        sig: Result<&str, Error>,
+    ) -> Result<(), WxLoginErr> {
+        self.handle_logout_with_auth(None, stoken, req, sig).await
+    }
+
+    /// Handle logout request, reusing the result of the middleware if the request is authenticated by it.
+    #[tracing::instrument(err(Debug), skip_all)]
+    pub(crate) async fn handle_logout_with_auth(
+        &self,
+        auth_res: Option<WxLoginAuthResult>,
+        stoken: &str,
+        req: &SigRequest<'_>,
+        sig: Result<&str, Error>,
+    ) -> Result<(), WxLoginErr> {
+        let cfg = self.cfg.load();
+        let login_info = self
+            .reuse_or_authenticate(&cfg, auth_res, stoken, req, sig, cfg.session_max_age)
+            .await
+            .map_err(auth_err_resp)?;
+        if let Some(store) = &cfg.session_store {
+            store
+                .revoke(
+                    &login_info.appid,
+                    &login_info.openid,
+                    &login_info.secret.session_id,
+                )
+                .await
+                .map_err(auth_err_resp)?;
+        }
+        Ok(())
+    }
+
+    /// List the active sessions of an openid in the order of issue time.
+    ///
+    /// It fails with [Error::Config] if no session store is configured.
+    pub async fn list_sessions(
+        &self,
+        appid: &str,
+        openid: &str,
+    ) -> Result<Vec<SessionRecord>, Error> {
+        let cfg = self.cfg.load();
+        let mut sessions = session_store(&cfg)?.list(appid, openid).await?;
+        sessions.sort_by_key(|v| v.issued_at);
+        Ok(sessions)
+    }
+
+    /// Revoke a session of an openid, return false if it is not found.
+    ///
+    /// It fails with [Error::Config] if no session store is configured.
+    pub async fn revoke_session(
+        &self,
+        appid: &str,
+        openid: &str,
+        session_id: &str,
+    ) -> Result<bool, Error> {
+        let cfg = self.cfg.load();
+        session_store(&cfg)?.revoke(appid, openid, session_id).await
+    }
+
+    /// Revoke all sessions of an openid, return the number of revoked sessions.
+    ///
+    /// It fails with [Error::Config] if no session store is configured.
+    pub async fn revoke_all_sessions(&self, appid: &str, openid: &str) -> Result<usize, Error> {
+        let cfg = self.cfg.load();
+        session_store(&cfg)?.revoke_all(appid, openid).await
+    }
+
+    /// Record the new session replacing the old one (if any) in the session store,
+    /// and revoke the oldest sessions beyond the max sessions per openid.
+    async fn record_session(
+        &self,
+        cfg: &Config,
+        record: SessionRecord,
+        replaced: Option<&str>,
+    ) -> Result<(), Error> {
+        let Some(store) = &cfg.session_store else {
+            return Ok(());
+        };
+        let (appid, openid, session_id) = (
+            record.appid.clone(),
+            record.openid.clone(),
+            record.session_id.clone(),
+        );
+        store.insert(record).await?;
+        if let Some(replaced) = replaced {
+            store.revoke(&appid, &openid, replaced).await?;
+        }
+        let Some(max) = cfg.max_sessions_per_openid else {
+            return Ok(());
+        };
+        let mut sessions = store.list(&appid, &openid).await?;
+        if sessions.len() <= max {
+            return Ok(());
+        }
+        sessions.retain(|v| v.session_id != session_id);
+        sessions.sort_by_key(|v| v.issued_at);
+        let evicted = sessions.len() + 1 - max;
+        for session in &sessions[..evicted] {
+            store.revoke(&appid, &openid, &session.session_id).await?;
+            tracing::info!(
+                event = "wx_login.session_evicted",
+                appid,
+                openid,
+                session_id = session.session_id,
+                "revoke the oldest session beyond max sessions per openid"
+            );
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn make_login_ok(
+        &self,
+        cfg: &Config,
+        app_info: &AppInfo,
+        openid: String,
+        unionid: Option<String>,
+        session_key: &[u8; 16],
+        extra: LoginExtra,
+        client: ClientInfo,
+        replaced: Option<&str>,
+    ) -> Result<WxLoginOk, WxLoginErr> {
+        let authority = Authority::new(app_info, &cfg.token_keys);
+        let format = TokenFormat::ST2;
+        let client_sess =
+            authority.make_client_session(format, &openid, unionid.as_deref(), session_key);
+        let record = SessionRecord {
+            appid: app_info.appid.clone(),
             openid: openid.clone(),
-            stoken: ["ST1".into(), appid, openid, client_sess.sess_token].join(":"),
+            session_id: client_sess.session_id.clone(),
+            issued_at: client_sess.sess_time,
+            last_seen: client_sess.sess_time,
+            client,
+            expires_at: session_expires_at(
+                client_sess.sess_time,
+                cfg.session_max_age,
+                cfg.refresh_max_age,
+            ),
+        };
+        self.record_session(cfg, record, replaced)
+            .await
+            .map_err(err_resp(500, "session-store-fail"))?;
+        Ok(WxLoginOk {
+            stoken: [
+                format.tag(),
+                &app_info.appid,
+                &openid,
+                &client_sess.sess_token,
+            ]
+            .join(":"),
+            openid,
+            unionid,
             skey: client_sess.sess_key,
+            expires_at: cfg.session_max_age.map(|max_age| {
+                (client_sess.sess_time + max_age)
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs()
+            }),
+            extra,
         })
     }
 
     /// Authenticate login status.
     #[tracing::instrument(err, ret, skip(self))]
-    pub fn authenticate(
+    pub async fn authenticate(
         &self,
         stoken: &str,
-        uri: &str,
+        req: &SigRequest<'_>,
         sig: Result<&str, Error>,
     ) -> Result<WxLoginInfo, Error> {
-        let (tag, appid, openid, token_str) =
-            stoken.split(":").next_tuple().ok_or("bad stoken format")?;
-        if tag != "ST1" {
-            return Err(format!("bad stoken tag:{tag}").into());
-        }
-        let app_info = self.cfg.app_map.get(appid).ok_or("appid not found")?;
-        let authority = Authority::new(app_info);
-        let secret = authority.auth_client_session(openid, token_str)?;
-        let mut sig_authed = false;
-        if self.cfg.auth_sig {
-            let (tag, ts_ms_str, nonce_str, sig_str) =
-                sig?.split(":").next_tuple().ok_or("bad sig format")?;
-            if tag != "SG1" {
-                return Err(format!("bad sig tag:{tag}").into());
+        let cfg = self.cfg.load();
+        self.authenticate_with_max_age(&cfg, stoken, req, sig, cfg.session_max_age)
+            .await
+    }
+
+    /// Reuse the result of the middleware, since authenticating the same request again
+    /// would be rejected as a replayed nonce.
+    ///
+    /// An expired session fails before the signature and nonce are checked,
+    /// so it is authenticated again with the max age (e.g. the one of refresh).
+    async fn reuse_or_authenticate(
+        &self,
+        cfg: &Config,
+        auth_res: Option<WxLoginAuthResult>,
+        stoken: &str,
+        req: &SigRequest<'_>,
+        sig: Result<&str, Error>,
+        max_age: Option<Duration>,
+    ) -> Result<WxLoginInfo, Error> {
+        match auth_res {
+            Some(Ok(info)) => match max_age {
+                Some(max_age) if SystemTime::now() > info.secret.client_sess_time + max_age => {
+                    Err(Error::SessionExpired)
+                }
+                _ => Ok(info),
+            },
+            Some(Err(Error::SessionExpired)) | None => {
+                self.authenticate_with_max_age(cfg, stoken, req, sig, max_age)
+                    .await
+            }
+            Some(Err(err)) => Err(err),
+        }
+    }
+
+    async fn authenticate_with_max_age(
+        &self,
+        cfg: &Config,
+        stoken: &str,
+        req: &SigRequest<'_>,
+        sig: Result<&str, Error>,
+        max_age: Option<Duration>,
+    ) -> Result<WxLoginInfo, Error> {
+        let (tag, appid, openid, token_str) = stoken
+            .split(":")
+            .next_tuple()
+            .ok_or(Error::StokenInvalid("bad stoken format".into()))?;
+        let format = TokenFormat::from_tag(tag)
+            .ok_or_else(|| Error::StokenInvalid(format!("bad stoken tag:{tag}")))?;
+        if format == TokenFormat::ST1
+            && cfg
+                .st1_accept_until
+                .is_some_and(|until| SystemTime::now() >= until)
+        {
+            return Err(Error::StokenFormatExpired);
+        }
+        let app_info = cfg
+            .get_app_info(appid)
+            .await?
+            .ok_or_else(|| Error::AppNotFound(appid.into()))?;
+        let authority = Authority::new(&app_info, &cfg.token_keys);
+        let secret = authority.auth_client_session(format, openid, token_str)?;
+        if let Some(max_age) = max_age {
+            if SystemTime::now() > secret.client_sess_time + max_age {
+                return Err(Error::SessionExpired);
+            }
+        }
+        let sig_key = BASE64.to_text(&secret.client_sess_key);
+        let sig_authed = if cfg.auth_sig {
+            self.check_sig(cfg, &authority, stoken, &sig_key, req, sig?)
+                .await?;
+            true
+        } else if let Ok(sig) = sig {
+            // verify the signature if present even though it is not required,
+            // so that SignedWxLoginInfo can be used on some handlers
+            self.check_sig(cfg, &authority, stoken, &sig_key, req, sig)
+                .await
+                .inspect_err(|e| tracing::debug!("ignore invalid sig: {e}"))
+                .is_ok()
+        } else {
+            false
+        };
+        // the session store is consulted only after the signature is checked,
+        // so that a leaked stoken alone can not update (or load) the store
+        if let Some(store) = &cfg.session_store {
+            if !store
+                .touch(appid, openid, &secret.session_id, SystemTime::now())
+                .await?
+            {
+                return Err(Error::SessionRevoked);
             }
-            authority.auth_client_sig(
-                &BASE64.to_text(&secret.client_sess_key),
-                uri,
-                ts_ms_str,
-                nonce_str,
-                sig_str,
-                |dur, _nonce| dur <= Duration::from_secs(self.cfg.sig_valid_secs),
-            )?;
-            sig_authed = true;
         }
         Ok(WxLoginInfo::new(WxLoginInfoInner {
             appid: appid.into(),
             openid: openid.into(),
+            unionid: secret.unionid.clone(),
             secret,
             sig_authed,
         }))
     }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn check_sig(
+        &self,
+        cfg: &Config,
+        authority: &Authority<'_>,
+        stoken: &str,
+        sig_key: &str,
+        req: &SigRequest<'_>,
+        sig: &str,
+    ) -> Result<(), Error> {
+        let (tag, ts_ms_str, nonce_str, sig_str) = sig
+            .split(":")
+            .next_tuple()
+            .ok_or(Error::SigInvalid("bad sig format".into()))?;
+        let scheme = SigScheme::from_tag(tag)
+            .filter(|scheme| cfg.sig_schemes.contains(scheme))
+            .ok_or_else(|| Error::SigInvalid(format!("bad sig tag:{tag}")))?;
+        let sig_valid_dur = Duration::from_secs(cfg.sig_valid_secs);
+        let sig_future_skew = Duration::from_secs(cfg.sig_future_skew_secs);
+        let nonce = authority.auth_client_sig(
+            sig_key,
+            scheme,
+            req,
+            ts_ms_str,
+            nonce_str,
+            sig_str,
+            sig_future_skew,
+            |dur, _nonce| dur <= sig_valid_dur,
+        )?;
+        if let Some(nonce_store) = &cfg.nonce_store {
+            if !nonce_store
+                .check_and_insert(stoken, nonce, sig_valid_dur + sig_future_skew)
+                .await?
+            {
+                return Err(Error::NonceReplayed);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn session_store(cfg: &Config) -> Result<&Arc<dyn SessionStore>, Error> {
+    cfg.session_store
+        .as_ref()
+        .ok_or_else(|| Error::Config("no session store is configured".into()))
+}
+
+/// The authentication result stashed by the middleware for the extractors.
+pub(crate) type WxLoginAuthResult = Result<WxLoginInfo, Error>;
+
+/// The extractor of [WxLoginInfo] for handlers serving both anonymous and logged-in users.
+///
+/// It yields None if no stoken is present, but still rejects a present-but-invalid one.
+#[derive(Debug, Clone)]
+pub struct OptionalWxLoginInfo(pub Option<WxLoginInfo>);
+
+/// The extractor of [WxLoginInfo] which requires the request signature to be verified,
+/// even if signature authentication is disabled by config.
+#[derive(Debug, Clone)]
+pub struct SignedWxLoginInfo(pub WxLoginInfo);
+
+impl std::ops::Deref for SignedWxLoginInfo {
+    type Target = WxLoginInfo;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// The rejection of the [WxLoginInfo] extractors, with the error kind to branch on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WxLoginRejection {
+    /// The authentication fails.
+    Auth(Error),
+    /// The authentication result is lost, e.g. the route is not wrapped by the middleware.
+    SessionLost,
+}
+
+impl WxLoginRejection {
+    /// Get the authentication error if any.
+    pub fn error(&self) -> Option<&Error> {
+        match self {
+            Self::Auth(err) => Some(err),
+            Self::SessionLost => None,
+        }
+    }
+
+    /// Make the error response of the rejection.
+    pub fn to_response(&self) -> WxLoginErr {
+        match self {
+            Self::Auth(err) => auth_err_resp(err.clone()),
+            Self::SessionLost => WxLoginErr {
+                status: 500,
+                code: "login-session-lost".into(),
+                message: AUTH_FAIL_MSG.into(),
+                detail: "".into(),
+                server_time: None,
+            },
+        }
+    }
+}
+
+impl From<Error> for WxLoginRejection {
+    fn from(err: Error) -> Self {
+        Self::Auth(err)
+    }
+}
+
+/// Extract [WxLoginInfo] from the authentication result stashed by the middleware.
+pub(crate) fn extract_login_info(
+    auth_res: Option<&WxLoginAuthResult>,
+) -> Result<WxLoginInfo, WxLoginRejection> {
+    match auth_res {
+        Some(Ok(login_info)) => Ok(login_info.clone()),
+        Some(Err(err)) => Err(err.clone().into()),
+        None => Err(WxLoginRejection::SessionLost),
+    }
+}
+
+/// Extract [OptionalWxLoginInfo], where has_stoken tells if the request has the stoken header.
+pub(crate) fn extract_optional_login_info(
+    auth_res: Option<&WxLoginAuthResult>,
+    has_stoken: bool,
+) -> Result<OptionalWxLoginInfo, WxLoginRejection> {
+    if has_stoken {
+        extract_login_info(auth_res).map(|v| OptionalWxLoginInfo(Some(v)))
+    } else {
+        Ok(OptionalWxLoginInfo(None))
+    }
+}
+
+/// Extract [SignedWxLoginInfo] from the authentication result stashed by the middleware.
+pub(crate) fn extract_signed_login_info(
+    auth_res: Option<&WxLoginAuthResult>,
+) -> Result<SignedWxLoginInfo, WxLoginRejection> {
+    let login_info = extract_login_info(auth_res)?;
+    if !login_info.sig_authed {
+        return Err(Error::SigRequired.into());
+    }
+    Ok(SignedWxLoginInfo(login_info))
+}
+
+/// Well-known errors of WeChat code2session API as (errcode, status, code).
+const WX_API_ERRORS: &[(i64, u16, &str)] = &[
+    (-1, 503, "wx-system-busy"),
+    (40029, 401, "wx-code-invalid"),
+    (40125, 500, "wx-app-secret-invalid"),
+    (40163, 401, "wx-code-used"),
+    (45011, 429, "wx-rate-limited"),
+    (40226, 403, "wx-user-blocked"),
+];
+
+fn api_err_resp(e: WxApiError) -> WxLoginErr {
+    match e {
+        WxApiError::Connect(_) | WxApiError::Call(_) => {
+            err_resp(500, "jscode2session-call-fail")(e)
+        }
+        WxApiError::Unavailable(_) => err_resp(503, "wechat-unavailable")(e),
+        WxApiError::Decode(_) => err_resp(401, "jscode2session-resp-fail")(e),
+        WxApiError::WeChat { errcode, .. } => {
+            let (status, code) = WX_API_ERRORS
+                .iter()
+                .find(|(c, _, _)| *c == errcode)
+                .map(|(_, status, code)| (*status, *code))
+                .unwrap_or((401, "jscode2session-wx-error"));
+            err_resp(status, code)(e)
+        }
+    }
+}
+
+/// Make the error response of a failed authentication.
+pub(crate) fn auth_err_resp(err: Error) -> WxLoginErr {
+    let status = match err {
+        Error::AppProviderFail(_) | Error::SessionStoreFail(_) | Error::NonceStoreFail(_) => 500,
+        _ => 401,
+    };
+    WxLoginErr {
+        status,
+        code: err.code().into(),
+        message: AUTH_FAIL_MSG.into(),
+        detail: err.to_string(),
+        server_time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .ok(),
+    }
 }
 
 fn err_resp<E: Display>(status: u16, code: &str) -> impl '_ + FnOnce(E) -> WxLoginErr {
@@ -158,35 +715,593 @@ fn err_resp<E: Display>(status: u16, code: &str) -> impl '_ + FnOnce(E) -> WxLog
         code: code.into(),
         message: LOGIN_FAIL_MSG.into(),
         detail: e.to_string(),
+        server_time: None,
     }
 }
 
-mod proto {
+#[cfg(test)]
+mod tests {
     use super::*;
+    use crate::core::hook::LoginHook;
+    use crate::core::nonce::NonceStore;
+    use crate::core::session::{ClientInfo, MemorySessionStore, SessionRecord, SessionStore};
+    use crate::core::testing::{fake_config, make_sg2_sig, FakeApiClient};
+    use async_trait::async_trait;
+    use tiny_crypto::sha1_hex;
 
-    #[derive(Serialize)]
-    pub(crate) struct Code2SessionRequest {
-        appid: String,
-        secret: String,
-        js_code: String,
-        grant_type: String,
-    }
+    #[derive(Debug)]
+    struct FakeLoginHook;
 
-    impl Code2SessionRequest {
-        pub(crate) fn from(appid: String, secret: String, code: String) -> Self {
-            Self {
-                appid,
-                secret,
-                js_code: code,
-                grant_type: "authorization_code".into(),
+    #[async_trait]
+    impl LoginHook for FakeLoginHook {
+        async fn on_login(&self, ctx: &LoginContext) -> Result<LoginExtra, WxLoginErr> {
+            if ctx.openid == "banned-openid" {
+                return Err(WxLoginErr {
+                    status: 403,
+                    code: "user-banned".into(),
+                    message: LOGIN_FAIL_MSG.into(),
+                    detail: "".into(),
+                    server_time: None,
+                });
             }
+            let mut extra = LoginExtra::new();
+            extra.insert("user_id".into(), 42.into());
+            extra.insert("openid".into(), "fake-openid".into());
+            Ok(extra)
+        }
+    }
+
+    fn make_wx_login() -> WxLogin {
+        WxLogin::new(fake_config().build())
+    }
+
+    fn make_sig(skey: &str, uri: &str) -> String {
+        make_sig_at(skey, uri, SystemTime::now())
+    }
+
+    fn make_sig_at(skey: &str, uri: &str, ts: SystemTime) -> String {
+        let ts = ts
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis()
+            .to_string();
+        let nonce = fastrand::u64(..).to_string();
+        let digest = sha1_hex!(format!("{uri}:{ts}:{nonce}:{skey}").as_bytes());
+        format!("SG1:{ts}:{nonce}:{digest}")
+    }
+
+    #[tokio::test]
+    async fn login_and_authenticate() {
+        let wx_login = make_wx_login();
+        let login_ok = wx_login
+            .handle_login("some_appid".into(), "good-code".into())
+            .await
+            .unwrap();
+        assert_eq!(login_ok.openid, "some-openid");
+        let sig = make_sig(&login_ok.skey, "/api?a=1");
+        let login_info = wx_login
+            .authenticate(
+                &login_ok.stoken,
+                &SigRequest::new("GET", "/api?a=1", b""),
+                Ok(&sig),
+            )
+            .await
+            .unwrap();
+        assert_eq!(login_info.openid, "some-openid");
+        assert_eq!(login_info.unionid, login_ok.unionid);
+        assert!(login_info.sig_authed);
+        assert!(wx_login
+            .authenticate(
+                &login_ok.stoken,
+                &SigRequest::new("GET", "/api?a=2", b""),
+                Ok(&sig)
+            )
+            .await
+            .is_err());
+        let err = wx_login
+            .authenticate(
+                &login_ok.stoken,
+                &SigRequest::new("GET", "/api?a=1", b""),
+                Ok(&sig),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "nonce-replayed");
+    }
+
+    #[derive(Debug)]
+    struct DownNonceStore;
+
+    #[async_trait]
+    impl NonceStore for DownNonceStore {
+        async fn check_and_insert(&self, _: &str, _: u64, _: Duration) -> Result<bool, Error> {
+            Err(Error::NonceStoreFail("connection refused".into()))
         }
     }
 
-    #[derive(Deserialize, Debug)]
-    pub(crate) struct Code2SessionResponse {
-        pub(crate) session_key: String,
-        pub(crate) openid: String,
-        pub(crate) _unionid: Option<String>,
+    #[tokio::test]
+    async fn nonce_store_fail() {
+        let wx_login = WxLogin::new(
+            Config::builder()
+                .with_app_info(AppInfo::from("some_appid".into(), "some_secret".into()))
+                .with_api_client(FakeApiClient)
+                .with_nonce_store(DownNonceStore)
+                .build(),
+        );
+        let login_ok = wx_login
+            .handle_login("some_appid".into(), "good-code".into())
+            .await
+            .unwrap();
+        let sig = make_sig(&login_ok.skey, "/api");
+        let err = wx_login
+            .authenticate(
+                &login_ok.stoken,
+                &SigRequest::new("GET", "/api", b""),
+                Ok(&sig),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "nonce-store-fail");
+        assert_eq!(auth_err_resp(err).status, 500);
+    }
+
+    #[derive(Debug)]
+    struct DownSessionStore;
+
+    #[async_trait]
+    impl SessionStore for DownSessionStore {
+        async fn insert(&self, _: SessionRecord) -> Result<(), Error> {
+            Ok(())
+        }
+        async fn touch(&self, _: &str, _: &str, _: &str, _: SystemTime) -> Result<bool, Error> {
+            Err(Error::SessionStoreFail("connection refused".into()))
+        }
+        async fn list(&self, _: &str, _: &str) -> Result<Vec<SessionRecord>, Error> {
+            Ok(vec![])
+        }
+        async fn revoke(&self, _: &str, _: &str, _: &str) -> Result<bool, Error> {
+            Ok(false)
+        }
+        async fn revoke_all(&self, _: &str, _: &str) -> Result<usize, Error> {
+            Ok(0)
+        }
+    }
+
+    #[tokio::test]
+    async fn session_store_after_sig() {
+        let wx_login = WxLogin::new(fake_config().with_session_store(DownSessionStore).build());
+        let login_ok = wx_login
+            .handle_login("some_appid".into(), "good-code".into())
+            .await
+            .unwrap();
+        let req = SigRequest::new("GET", "/api", b"");
+        let sig = make_sig(&login_ok.skey, "/other");
+        let err = wx_login
+            .authenticate(&login_ok.stoken, &req, Ok(&sig))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "sig-mismatch");
+        let sig = make_sig(&login_ok.skey, "/api");
+        let err = wx_login
+            .authenticate(&login_ok.stoken, &req, Ok(&sig))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "session-store-fail");
+    }
+
+    #[tokio::test]
+    async fn login_fail() {
+        let wx_login = make_wx_login();
+        let err = wx_login
+            .handle_login("other_appid".into(), "good-code".into())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "appid-not-found");
+        let err = wx_login
+            .handle_login("some_appid".into(), "bad-code".into())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "jscode2session-resp-fail");
+        let err = wx_login
+            .handle_login("some_appid".into(), "used-code".into())
+            .await
+            .unwrap_err();
+        assert_eq!((err.status, err.code.as_str()), (401, "wx-code-used"));
+    }
+
+    #[tokio::test]
+    async fn login_hook() {
+        let wx_login = WxLogin::new(
+            Config::builder()
+                .with_app_info(AppInfo::from("some_appid".into(), "some_secret".into()))
+                .with_api_client(FakeApiClient)
+                .with_login_hook(FakeLoginHook)
+                .build(),
+        );
+        let login_ok = wx_login
+            .handle_login("some_appid".into(), "good-code".into())
+            .await
+            .unwrap();
+        let login_json = serde_json::to_value(&login_ok).unwrap();
+        assert_eq!(login_json["user_id"], 42);
+        assert_eq!(login_json["openid"], "some-openid");
+        let err = wx_login
+            .handle_login("some_appid".into(), "banned-code".into())
+            .await
+            .unwrap_err();
+        assert_eq!((err.status, err.code.as_str()), (403, "user-banned"));
+    }
+
+    #[tokio::test]
+    async fn session_expired() {
+        let wx_login = WxLogin::new(
+            Config::builder()
+                .with_app_info(AppInfo::from("some_appid".into(), "some_secret".into()))
+                .with_api_client(FakeApiClient)
+                .with_auth_sig(false)
+                .with_session_max_age(Duration::ZERO)
+                .build(),
+        );
+        let login_ok = wx_login
+            .handle_login("some_appid".into(), "good-code".into())
+            .await
+            .unwrap();
+        assert!(login_ok.expires_at.is_some());
+        let err = wx_login
+            .authenticate(
+                &login_ok.stoken,
+                &SigRequest::new("GET", "/api", b""),
+                Err(Error::SigMissing),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "session-expired");
+    }
+
+    #[tokio::test]
+    async fn refresh_session() {
+        let wx_login = make_wx_login();
+        let login_ok = wx_login
+            .handle_login("some_appid".into(), "good-code".into())
+            .await
+            .unwrap();
+        let sig = make_sig(&login_ok.skey, "/login/refresh");
+        let refresh_ok = wx_login
+            .handle_refresh(
+                &login_ok.stoken,
+                &SigRequest::new("POST", "/login/refresh", b""),
+                Ok(&sig),
+            )
+            .await
+            .unwrap();
+        assert_eq!(refresh_ok.openid, login_ok.openid);
+        assert_eq!(refresh_ok.unionid, login_ok.unionid);
+        assert_ne!(refresh_ok.skey, login_ok.skey);
+        let sig = make_sig(&refresh_ok.skey, "/api");
+        let login_info = wx_login
+            .authenticate(
+                &refresh_ok.stoken,
+                &SigRequest::new("GET", "/api", b""),
+                Ok(&sig),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            login_info.secret.session_key,
+            BASE64.from_text("HyVFkGl5F5OQWJZZaNzBBg==").unwrap()[..]
+        );
+        let err = wx_login
+            .handle_refresh(
+                &login_ok.stoken,
+                &SigRequest::new("POST", "/login/refresh", b""),
+                Ok("SG1:0:0:bad"),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.status, 401);
+    }
+
+    #[tokio::test]
+    async fn session_store_logout() {
+        let wx_login = WxLogin::new(
+            Config::builder()
+                .with_app_info(AppInfo::from("some_appid".into(), "some_secret".into()))
+                .with_api_client(FakeApiClient)
+                .with_session_store(MemorySessionStore::new())
+                .build(),
+        );
+        let login_ok = wx_login
+            .handle_login("some_appid".into(), "good-code".into())
+            .await
+            .unwrap();
+        let auth = |stoken: &str, skey: &str| {
+            let sig = make_sig(skey, "/api");
+            let (wx_login, stoken) = (&wx_login, stoken.to_string());
+            async move {
+                wx_login
+                    .authenticate(&stoken, &SigRequest::new("GET", "/api", b""), Ok(&sig))
+                    .await
+            }
+        };
+        auth(&login_ok.stoken, &login_ok.skey).await.unwrap();
+        // the refreshed session replaces the old one
+        let sig = make_sig(&login_ok.skey, "/login/refresh");
+        let refresh_ok = wx_login
+            .handle_refresh(
+                &login_ok.stoken,
+                &SigRequest::new("POST", "/login/refresh", b""),
+                Ok(&sig),
+            )
+            .await
+            .unwrap();
+        let err = auth(&login_ok.stoken, &login_ok.skey).await.unwrap_err();
+        assert_eq!(err, Error::SessionRevoked);
+        auth(&refresh_ok.stoken, &refresh_ok.skey).await.unwrap();
+        let sig = make_sig(&refresh_ok.skey, "/login/logout");
+        wx_login
+            .handle_logout(
+                &refresh_ok.stoken,
+                &SigRequest::new("POST", "/login/logout", b""),
+                Ok(&sig),
+            )
+            .await
+            .unwrap();
+        let err = auth(&refresh_ok.stoken, &refresh_ok.skey)
+            .await
+            .unwrap_err();
+        assert_eq!(err, Error::SessionRevoked);
+        assert_eq!(auth_err_resp(err).code, "session-revoked");
+    }
+
+    #[tokio::test]
+    async fn session_listing_and_limit() {
+        let wx_login = WxLogin::new(
+            Config::builder()
+                .with_app_info(AppInfo::from("some_appid".into(), "some_secret".into()))
+                .with_api_client(FakeApiClient)
+                .with_session_store(MemorySessionStore::new())
+                .with_max_sessions_per_openid(2)
+                // the fake client accepts the same code repeatedly
+                .with_login_dedup_ttl(Duration::ZERO)
+                .build(),
+        );
+        let client = ClientInfo {
+            ip: Some("10.0.0.1".into()),
+            user_agent: Some("wechat".into()),
+        };
+        let login = || async {
+            wx_login
+                .handle_login_with_client("some_appid".into(), "good-code".into(), client.clone())
+                .await
+                .unwrap()
+        };
+        let login_ok1 = login().await;
+        let openid = login_ok1.openid.clone();
+        let sessions = wx_login.list_sessions("some_appid", &openid).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].client, client);
+        let sig = make_sig(&login_ok1.skey, "/api");
+        let login_info = wx_login
+            .authenticate(
+                &login_ok1.stoken,
+                &SigRequest::new("GET", "/api", b""),
+                Ok(&sig),
+            )
+            .await
+            .unwrap();
+        let sessions = wx_login.list_sessions("some_appid", &openid).await.unwrap();
+        assert_eq!(sessions[0].session_id, login_info.secret.session_id);
+        assert!(sessions[0].last_seen >= sessions[0].issued_at);
+        // the sessions beyond the limit are evicted
+        let login_ok2 = login().await;
+        let login_ok3 = login().await;
+        assert_ne!(login_ok2.stoken, login_ok3.stoken);
+        let sessions = wx_login.list_sessions("some_appid", &openid).await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        let revoked = sessions[0].session_id.clone();
+        assert!(wx_login
+            .revoke_session("some_appid", &openid, &revoked)
+            .await
+            .unwrap());
+        assert!(!wx_login
+            .revoke_session("some_appid", &openid, &revoked)
+            .await
+            .unwrap());
+        let n = wx_login
+            .revoke_all_sessions("some_appid", &openid)
+            .await
+            .unwrap();
+        assert_eq!(n, 1);
+        let err = make_wx_login()
+            .list_sessions("some_appid", &openid)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "config-invalid");
+    }
+
+    #[tokio::test]
+    async fn sig_future_skew() {
+        let wx_login = make_wx_login();
+        let login_ok = wx_login
+            .handle_login("some_appid".into(), "good-code".into())
+            .await
+            .unwrap();
+        let sig = make_sig_at(
+            &login_ok.skey,
+            "/api",
+            SystemTime::now() + Duration::from_secs(30),
+        );
+        assert!(wx_login
+            .authenticate(
+                &login_ok.stoken,
+                &SigRequest::new("GET", "/api", b""),
+                Ok(&sig)
+            )
+            .await
+            .is_ok());
+        let sig = make_sig_at(
+            &login_ok.skey,
+            "/api",
+            SystemTime::now() + Duration::from_secs(120),
+        );
+        let err = wx_login
+            .authenticate(
+                &login_ok.stoken,
+                &SigRequest::new("GET", "/api", b""),
+                Ok(&sig),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "sig-ts-in-future");
+        assert!(auth_err_resp(err).server_time.is_some());
+    }
+
+    #[tokio::test]
+    async fn api_options_keep_custom_client() {
+        // the api options only configure the default client
+        let wx_login = WxLogin::new(
+            fake_config()
+                .with_api_base_url("http://127.0.0.1:1")
+                .with_api_timeout(Duration::from_millis(1))
+                .build(),
+        );
+        assert!(wx_login
+            .handle_login("some_appid".into(), "good-code".into())
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn accept_baseline_st1_token() {
+        // minted by the baseline release for (some_appid, some-openid) with session key [7; 16]
+        let stoken = "ST1:some_appid:some-openid:WKj+3GECNchzIuqWsy2UGhwwN796VUezjXXYa8HPhW8=";
+        let skey = "mGMeTuICwI2tUg9VWsUfug==";
+        let wx_login = make_wx_login();
+        let sig = make_sig(skey, "/api");
+        let login_info = wx_login
+            .authenticate(stoken, &SigRequest::new("GET", "/api", b""), Ok(&sig))
+            .await
+            .unwrap();
+        assert_eq!(login_info.openid, "some-openid");
+        assert_eq!(login_info.unionid, None);
+        let wx_login = WxLogin::new(
+            fake_config()
+                .with_st1_accept_until(SystemTime::now() - Duration::from_secs(1))
+                .build(),
+        );
+        let sig = make_sig(skey, "/api");
+        let err = wx_login
+            .authenticate(stoken, &SigRequest::new("GET", "/api", b""), Ok(&sig))
+            .await
+            .unwrap_err();
+        assert_eq!(err, Error::StokenFormatExpired);
+    }
+
+    #[tokio::test]
+    async fn sig_scheme_sg2() {
+        let wx_login = make_wx_login();
+        let login_ok = wx_login
+            .handle_login("some_appid".into(), "good-code".into())
+            .await
+            .unwrap();
+        let sig = make_sg2_sig(&login_ok.skey, "POST", "/api", b"{\"a\":1}");
+        let (head, _) = sig.rsplit_once(':').unwrap();
+        let err = wx_login
+            .authenticate(
+                &login_ok.stoken,
+                &SigRequest::new("POST", "/api", b"{\"a\":1}"),
+                Ok(&format!("{head}:not-hex")),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "sig-invalid");
+        let err = wx_login
+            .authenticate(
+                &login_ok.stoken,
+                &SigRequest::new("POST", "/api", b"{\"a\":2}"),
+                Ok(&sig),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err, Error::SigMismatch);
+        let login_info = wx_login
+            .authenticate(
+                &login_ok.stoken,
+                &SigRequest::new("POST", "/api", b"{\"a\":1}"),
+                Ok(&sig),
+            )
+            .await
+            .unwrap();
+        assert!(login_info.sig_authed);
+    }
+
+    #[tokio::test]
+    async fn fallback_secret() {
+        let make_wx_login = |app_info: AppInfo| {
+            WxLogin::new(
+                Config::builder()
+                    .with_app_info(app_info)
+                    .with_api_client(FakeApiClient)
+                    .build(),
+            )
+        };
+        let wx_login = make_wx_login(AppInfo::from("some_appid".into(), "new_secret".into()));
+        let err = wx_login
+            .handle_login("some_appid".into(), "good-code".into())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "wx-app-secret-invalid");
+        let wx_login = make_wx_login(
+            AppInfo::from("some_appid".into(), "new_secret".into())
+                .with_fallback_secret("some_secret".into()),
+        );
+        assert!(wx_login
+            .handle_login("some_appid".into(), "good-code".into())
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn optional_sig_and_extractors() {
+        let wx_login = WxLogin::new(
+            Config::builder()
+                .with_app_info(AppInfo::from("some_appid".into(), "some_secret".into()))
+                .with_api_client(FakeApiClient)
+                .with_auth_sig(false)
+                .build(),
+        );
+        let login_ok = wx_login
+            .handle_login("some_appid".into(), "good-code".into())
+            .await
+            .unwrap();
+        let req = SigRequest::new("GET", "/api", b"");
+        let (wx_login, stoken, req) = (&wx_login, &login_ok.stoken, &req);
+        let authenticate = move |sig: Result<String, Error>| async move {
+            wx_login
+                .authenticate(stoken, req, sig.as_deref().map_err(Clone::clone))
+                .await
+        };
+        let unsigned = authenticate(Err(Error::SigMissing)).await;
+        assert!(!unsigned.as_ref().unwrap().sig_authed);
+        let bad_signed = authenticate(Ok("SG1:1:2:bad".into())).await;
+        assert!(!bad_signed.as_ref().unwrap().sig_authed);
+        let signed = authenticate(Ok(make_sig(&login_ok.skey, "/api"))).await;
+        assert!(signed.as_ref().unwrap().sig_authed);
+
+        assert_eq!(
+            extract_signed_login_info(Some(&unsigned)).unwrap_err(),
+            WxLoginRejection::Auth(Error::SigRequired)
+        );
+        assert!(extract_signed_login_info(Some(&signed)).is_ok());
+        let failed = Err(Error::StokenInvalid("bad stoken".into()));
+        assert!(extract_optional_login_info(Some(&failed), false)
+            .unwrap()
+            .0
+            .is_none());
+        assert!(extract_optional_login_info(Some(&failed), true).is_err());
+        assert!(extract_optional_login_info(Some(&signed), true)
+            .unwrap()
+            .0
+            .is_some());
     }
 }
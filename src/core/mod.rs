@@ -1,3 +1,17 @@
 pub(crate) mod config;
+pub(crate) mod config_file;
+pub(crate) mod dedup;
+pub(crate) mod hook;
 pub(crate) mod login;
-pub(crate) mod security;
\ No newline at end of file
+pub(crate) mod nonce;
+pub(crate) mod policy;
+pub(crate) mod provider;
+pub(crate) mod reload;
+pub(crate) mod render;
+pub(crate) mod security;
+pub(crate) mod session;
+#[cfg(feature = "sqlite")]
+pub(crate) mod sqlite_session;
+#[cfg(test)]
+pub(crate) mod testing;
+pub(crate) mod wx_api;
@@ -0,0 +1,93 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+
+use crate::core::security::Error;
+
+/// A store of seen signature nonces, which is used to reject replayed requests.
+///
+/// The default implementation is the in-memory [MemoryNonceStore], one can implement this trait
+/// to share the nonces among a cluster (e.g. using redis).
+#[async_trait]
+pub trait NonceStore: Send + Sync + Debug {
+    /// Record the nonce of a session token.
+    ///
+    /// Return false if the (stoken, nonce) pair has already been seen within ttl,
+    /// or [Error::NonceStoreFail] if the store is unavailable.
+    async fn check_and_insert(
+        &self,
+        stoken: &str,
+        nonce: u64,
+        ttl: Duration,
+    ) -> Result<bool, Error>;
+}
+
+const SHARD_NUM: usize = 16;
+const MIN_PRUNE_LEN: usize = 1024;
+
+/// An in-memory [NonceStore] with sharded TTL maps.
+#[derive(Debug)]
+pub struct MemoryNonceStore {
+    shards: Vec<Mutex<NonceShard>>,
+}
+
+#[derive(Debug, Default)]
+struct NonceShard {
+    map: HashMap<(String, u64), Instant>,
+    prune_len: usize,
+}
+
+impl Default for MemoryNonceStore {
+    fn default() -> Self {
+        Self {
+            shards: (0..SHARD_NUM).map(|_| Default::default()).collect(),
+        }
+    }
+}
+
+impl MemoryNonceStore {
+    /// Create an empty MemoryNonceStore.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn shard(&self, stoken: &str, nonce: u64) -> &Mutex<NonceShard> {
+        let mut hasher = DefaultHasher::new();
+        (stoken, nonce).hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+}
+
+#[async_trait]
+impl NonceStore for MemoryNonceStore {
+    async fn check_and_insert(
+        &self,
+        stoken: &str,
+        nonce: u64,
+        ttl: Duration,
+    ) -> Result<bool, Error> {
+        let now = Instant::now();
+        let mut shard = self.shard(stoken, nonce).lock().unwrap();
+        if shard.map.len() >= shard.prune_len {
+            shard.map.retain(|_, expire| *expire > now);
+            shard.prune_len = (shard.map.len() * 2).max(MIN_PRUNE_LEN);
+        }
+        Ok(match shard.map.get_mut(&(stoken.into(), nonce)) {
+            Some(expire) if *expire > now => false,
+            Some(expire) => {
+                *expire = now + ttl;
+                true
+            }
+            None => {
+                shard.map.insert((stoken.into(), nonce), now + ttl);
+                true
+            }
+        })
+    }
+}
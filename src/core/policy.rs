@@ -0,0 +1,111 @@
+/// The path-based policy deciding which requests require login.
+///
+/// A pattern is either a glob, where `*` matches within one path segment and `**` matches
+/// any number of segments (e.g. `/api/**`, `/user/*/profile`), or a literal path.
+/// Public patterns take precedence over protected ones, and the paths matching neither
+/// follow the default.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AuthPolicy {
+    protected: Vec<String>,
+    public: Vec<String>,
+    required_by_default: bool,
+}
+
+impl AuthPolicy {
+    pub(crate) fn add_protected(&mut self, pattern: &str) {
+        self.protected.push(pattern.into());
+    }
+
+    pub(crate) fn add_public(&mut self, pattern: &str) {
+        self.public.push(pattern.into());
+    }
+
+    pub(crate) fn clear_protected(&mut self) {
+        self.protected.clear();
+    }
+
+    pub(crate) fn clear_public(&mut self) {
+        self.public.clear();
+    }
+
+    pub(crate) fn set_required_by_default(&mut self, required: bool) {
+        self.required_by_default = required;
+    }
+
+    pub(crate) fn patterns(&self) -> impl Iterator<Item = &String> {
+        self.protected.iter().chain(self.public.iter())
+    }
+
+    /// Check if the request of the path requires login.
+    pub(crate) fn requires_login(&self, path: &str) -> bool {
+        let matches = |pattern: &String| path_matches(pattern, path);
+        if self.public.iter().any(matches) {
+            false
+        } else if self.protected.iter().any(matches) {
+            true
+        } else {
+            self.required_by_default
+        }
+    }
+}
+
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    segments_match(&pattern, &path)
+}
+
+fn segments_match(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| segments_match(rest, &path[i..])),
+        Some((seg, rest)) => match path.split_first() {
+            Some((path_seg, path_rest)) => {
+                segment_matches(seg.as_bytes(), path_seg.as_bytes())
+                    && segments_match(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn segment_matches(pattern: &[u8], seg: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => seg.is_empty(),
+        Some((b'*', rest)) => (0..=seg.len()).any(|i| segment_matches(rest, &seg[i..])),
+        Some((c, rest)) => seg.first() == Some(c) && segment_matches(rest, &seg[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_patterns() {
+        assert!(path_matches("/api/**", "/api"));
+        assert!(path_matches("/api/**", "/api/"));
+        assert!(path_matches("/api/**", "/api/a/b"));
+        assert!(!path_matches("/api/**", "/apis/a"));
+        assert!(path_matches("/user/*/profile", "/user/123/profile"));
+        assert!(!path_matches("/user/*/profile", "/user/1/2/profile"));
+        assert!(path_matches("/files/*.png", "/files/a.png"));
+        assert!(!path_matches("/files/*.png", "/files/a.jpg"));
+        assert!(path_matches("/health", "/health"));
+        assert!(!path_matches("/health", "/health/x"));
+    }
+
+    #[test]
+    fn auth_policy() {
+        let mut policy = AuthPolicy::default();
+        policy.add_protected("/api/**");
+        policy.add_public("/api/health");
+        assert!(policy.requires_login("/api/user"));
+        assert!(!policy.requires_login("/api/health"));
+        assert!(!policy.requires_login("/index.html"));
+        policy.set_required_by_default(true);
+        policy.add_public("/public/**");
+        assert!(policy.requires_login("/index.html"));
+        assert!(!policy.requires_login("/public/a.js"));
+    }
+}
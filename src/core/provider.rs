@@ -0,0 +1,186 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+
+use crate::core::{config::AppInfo, security::Error};
+
+/// A provider of [AppInfo] looked up by appid, which is consulted on login and authentication.
+///
+/// The static app map of [Config](crate::core::config::Config) is the default implementation,
+/// one can implement this trait to load apps from a database, and wrap it with
+/// [CachedAppInfoProvider] to reduce the lookups.
+#[async_trait]
+pub trait AppInfoProvider: Send + Sync + Debug {
+    /// Get the AppInfo of the appid, or None if the appid is unknown.
+    ///
+    /// Return [Error::AppProviderFail] if the lookup fails (e.g. the database is down).
+    async fn get_app_info(&self, appid: &str) -> Result<Option<AppInfo>, Error>;
+}
+
+#[async_trait]
+impl AppInfoProvider for HashMap<String, AppInfo> {
+    async fn get_app_info(&self, appid: &str) -> Result<Option<AppInfo>, Error> {
+        Ok(self.get(appid).cloned())
+    }
+}
+
+/// An [AppInfoProvider] looking up the static app map before the custom provider.
+#[derive(Debug)]
+pub(crate) struct StaticFirstAppInfoProvider {
+    app_map: Arc<HashMap<String, AppInfo>>,
+    provider: Arc<dyn AppInfoProvider>,
+}
+
+impl StaticFirstAppInfoProvider {
+    pub(crate) fn new(
+        app_map: Arc<HashMap<String, AppInfo>>,
+        provider: Arc<dyn AppInfoProvider>,
+    ) -> Self {
+        Self { app_map, provider }
+    }
+}
+
+#[async_trait]
+impl AppInfoProvider for StaticFirstAppInfoProvider {
+    async fn get_app_info(&self, appid: &str) -> Result<Option<AppInfo>, Error> {
+        match self.app_map.get(appid) {
+            Some(app_info) => Ok(Some(app_info.clone())),
+            None => self.provider.get_app_info(appid).await,
+        }
+    }
+}
+
+const MIN_PRUNE_LEN: usize = 1024;
+
+/// An [AppInfoProvider] wrapper caching the results of the inner provider.
+///
+/// Found apps are cached for ttl and unknown appids for negative ttl,
+/// while errors of the inner provider are not cached.
+#[derive(Debug)]
+pub struct CachedAppInfoProvider<P> {
+    inner: P,
+    ttl: Duration,
+    negative_ttl: Duration,
+    cache: Mutex<AppInfoCache>,
+}
+
+#[derive(Debug, Default)]
+struct AppInfoCache {
+    map: HashMap<String, (Option<AppInfo>, Instant)>,
+    prune_len: usize,
+}
+
+impl<P: AppInfoProvider> CachedAppInfoProvider<P> {
+    /// Create a CachedAppInfoProvider with the ttl of found apps.
+    ///
+    /// The default negative ttl is 60 seconds.
+    pub fn new(inner: P, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            negative_ttl: Duration::from_secs(60),
+            cache: Default::default(),
+        }
+    }
+
+    /// Set the ttl of unknown appids, and zero disables the negative caching.
+    pub fn with_negative_ttl(mut self, negative_ttl: Duration) -> Self {
+        self.negative_ttl = negative_ttl;
+        self
+    }
+
+    /// Remove the cached result of the appid, e.g. after the app secret is changed.
+    pub fn invalidate(&self, appid: &str) {
+        self.cache.lock().unwrap().map.remove(appid);
+    }
+}
+
+#[async_trait]
+impl<P: AppInfoProvider> AppInfoProvider for CachedAppInfoProvider<P> {
+    async fn get_app_info(&self, appid: &str) -> Result<Option<AppInfo>, Error> {
+        let now = Instant::now();
+        if let Some((app_info, expire)) = self.cache.lock().unwrap().map.get(appid) {
+            if *expire > now {
+                return Ok(app_info.clone());
+            }
+        }
+        let app_info = self.inner.get_app_info(appid).await?;
+        let ttl = match app_info {
+            Some(_) => self.ttl,
+            None => self.negative_ttl,
+        };
+        if !ttl.is_zero() {
+            let mut cache = self.cache.lock().unwrap();
+            if cache.map.len() >= cache.prune_len {
+                cache.map.retain(|_, (_, expire)| *expire > now);
+                cache.prune_len = (cache.map.len() * 2).max(MIN_PRUNE_LEN);
+            }
+            cache
+                .map
+                .insert(appid.into(), (app_info.clone(), now + ttl));
+        }
+        Ok(app_info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::ConfigBuilder;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[derive(Debug, Default)]
+    struct CountingProvider(AtomicU32);
+
+    #[async_trait]
+    impl AppInfoProvider for CountingProvider {
+        async fn get_app_info(&self, appid: &str) -> Result<Option<AppInfo>, Error> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            match appid {
+                "some_appid" => Ok(Some(AppInfo::from(appid.into(), "some_secret".into()))),
+                "bad_appid" => Err(Error::AppProviderFail("db is down".into())),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn cached_provider() {
+        let provider =
+            CachedAppInfoProvider::new(CountingProvider::default(), Duration::from_secs(3600));
+        for _ in 0..3 {
+            let app_info = provider.get_app_info("some_appid").await.unwrap();
+            assert_eq!(app_info.unwrap().secret.0, "some_secret");
+            assert!(provider.get_app_info("other").await.unwrap().is_none());
+            assert!(provider.get_app_info("bad_appid").await.is_err());
+        }
+        assert_eq!(provider.inner.0.load(Ordering::SeqCst), 5);
+        provider.invalidate("some_appid");
+        provider.get_app_info("some_appid").await.unwrap();
+        assert_eq!(provider.inner.0.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn config_app_provider() {
+        let cfg = ConfigBuilder::new()
+            .with_app_info(AppInfo::from("static_appid".into(), "static_secret".into()))
+            .with_app_provider(CountingProvider::default())
+            .build();
+        let app_info = cfg.get_app_info("static_appid").await.unwrap();
+        assert_eq!(app_info.unwrap().secret.0, "static_secret");
+        let app_info = cfg.get_app_info("some_appid").await.unwrap();
+        assert_eq!(app_info.unwrap().secret.0, "some_secret");
+        assert!(cfg.get_app_info("bad_appid").await.is_err());
+
+        let cfg = ConfigBuilder::new()
+            .with_app_info(AppInfo::from("static_appid".into(), "static_secret".into()))
+            .build();
+        assert!(cfg.get_app_info("static_appid").await.unwrap().is_some());
+        assert!(cfg.get_app_info("some_appid").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use arc_swap::ArcSwap;
+
+use crate::core::{config::Config, security::Error};
+
+type ConfigLoader = dyn Fn() -> Result<Config, Error> + Send + Sync;
+
+/// A reloadable handle of [Config], which is swapped atomically and observed by all clones.
+///
+/// Requests in flight keep using the config they started with.
+#[derive(Clone)]
+pub struct ConfigHandle {
+    cfg: Arc<ArcSwap<Config>>,
+    loader: Option<Arc<ConfigLoader>>,
+}
+
+impl fmt::Debug for ConfigHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConfigHandle")
+            .field("cfg", &self.cfg.load())
+            .field("has_loader", &self.loader.is_some())
+            .finish()
+    }
+}
+
+impl From<Config> for ConfigHandle {
+    fn from(cfg: Config) -> Self {
+        Self::new(cfg)
+    }
+}
+
+impl From<Arc<Config>> for ConfigHandle {
+    fn from(cfg: Arc<Config>) -> Self {
+        Self {
+            cfg: Arc::new(ArcSwap::new(cfg)),
+            loader: None,
+        }
+    }
+}
+
+impl ConfigHandle {
+    /// Create a ConfigHandle with a fixed initial config, which can be replaced by [store](Self::store).
+    pub fn new(cfg: Config) -> Self {
+        Arc::new(cfg).into()
+    }
+
+    /// Create a ConfigHandle with a loader, which is called now and on every [reload](Self::reload).
+    ///
+    /// The state in memory of the built-in parts (e.g. the seen nonces of [MemoryNonceStore](crate::core::nonce::MemoryNonceStore))
+    /// is carried over to the reloaded config, see [store](Self::store).
+    ///
+    /// ```ignore
+    /// let handle = ConfigHandle::with_loader(|| {
+    ///     Ok(Config::builder().with_file("wx_login.toml")?.try_with_env_var()?.build())
+    /// })?;
+    /// ```
+    pub fn with_loader(
+        loader: impl Fn() -> Result<Config, Error> + Send + Sync + 'static,
+    ) -> Result<Self, Error> {
+        let cfg = loader()?;
+        cfg.validate()?;
+        Ok(Self {
+            cfg: Arc::new(ArcSwap::from_pointee(cfg)),
+            loader: Some(Arc::new(loader)),
+        })
+    }
+
+    /// Get the current config.
+    pub fn load(&self) -> Arc<Config> {
+        self.cfg.load_full()
+    }
+
+    /// Replace the current config, which is rejected if the new config is invalid.
+    ///
+    /// The built-in in-memory parts of the new config (the [MemoryNonceStore](crate::core::nonce::MemoryNonceStore),
+    /// [MemorySessionStore](crate::core::session::MemorySessionStore), the default api client with its circuit breaker,
+    /// and the login dedup cache) are replaced by the ones of the current config to keep their state,
+    /// unless their options are changed.
+    pub fn store(&self, mut cfg: Config) -> Result<(), Error> {
+        cfg.validate()
+            .inspect_err(|e| tracing::warn!("reject invalid config: {e}"))?;
+        cfg.inherit_state(&self.cfg.load());
+        tracing::info!("use {:?}", cfg);
+        self.cfg.store(Arc::new(cfg));
+        Ok(())
+    }
+
+    /// Reload the config by the loader, and keep the current config if it fails.
+    pub fn reload(&self) -> Result<(), Error> {
+        let loader = self
+            .loader
+            .as_ref()
+            .ok_or(Error::Config("no config loader".into()))?;
+        let cfg = loader().inspect_err(|e| tracing::warn!("load config fail: {e}"))?;
+        self.store(cfg)
+    }
+
+    /// Spawn a tokio task to [reload](Self::reload) the config whenever the modified time
+    /// of the file changes, which is polled at the interval.
+    pub fn watch_file(
+        &self,
+        path: impl Into<PathBuf>,
+        interval: Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        let path = path.into();
+        let handle = self.clone();
+        tokio::spawn(async move {
+            let mtime = |path: &PathBuf| -> Option<SystemTime> {
+                std::fs::metadata(path).and_then(|m| m.modified()).ok()
+            };
+            let mut last_mtime = mtime(&path);
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let cur_mtime = mtime(&path);
+                if cur_mtime.is_some() && cur_mtime != last_mtime {
+                    last_mtime = cur_mtime;
+                    tracing::info!("config file {path:?} changed, reload it");
+                    let _ = handle.reload();
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::AppInfo;
+    use crate::core::nonce::NonceStore;
+    use crate::core::session::{MemorySessionStore, SessionRecord};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn reload_config() {
+        let counter = Arc::new(AtomicU32::new(0));
+        let loader_counter = counter.clone();
+        let handle = ConfigHandle::with_loader(move || {
+            let n = loader_counter.fetch_add(1, Ordering::SeqCst);
+            let path = if n == 2 { "bad-path" } else { "/login" };
+            Ok(Config::builder()
+                .with_app_info(AppInfo::from(format!("app{n}"), "secret".into()))
+                .with_login_path(path)
+                .build())
+        })
+        .unwrap();
+        let cloned = handle.clone();
+        assert!(cloned.load().app_map.contains_key("app0"));
+        handle.reload().unwrap();
+        assert!(cloned.load().app_map.contains_key("app1"));
+        assert!(handle.reload().is_err());
+        assert!(cloned.load().app_map.contains_key("app1"));
+        assert!(ConfigHandle::new(Config::default())
+            .store(Config::builder().with_sig_schemes(&[]).build())
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn reload_keeps_memory_state() {
+        let handle = ConfigHandle::with_loader(|| {
+            Ok(Config::builder()
+                .with_session_store(MemorySessionStore::new())
+                .build())
+        })
+        .unwrap();
+        let ttl = Duration::from_secs(60);
+        let nonce_store = handle.load().nonce_store.clone().unwrap();
+        assert!(nonce_store.check_and_insert("st", 1, ttl).await.unwrap());
+        let session_store = handle.load().session_store.clone().unwrap();
+        session_store
+            .insert(SessionRecord {
+                appid: "app".into(),
+                openid: "u1".into(),
+                session_id: "s1".into(),
+                issued_at: SystemTime::now(),
+                last_seen: SystemTime::now(),
+                client: Default::default(),
+                expires_at: None,
+            })
+            .await
+            .unwrap();
+        let old_cfg = handle.load();
+        handle.reload().unwrap();
+        let cfg = handle.load();
+        assert!(Arc::ptr_eq(&cfg.login_dedup, &old_cfg.login_dedup));
+        let nonce_store = cfg.nonce_store.as_ref().unwrap();
+        assert!(!nonce_store.check_and_insert("st", 1, ttl).await.unwrap());
+        let session_store = cfg.session_store.as_ref().unwrap();
+        assert_eq!(session_store.list("app", "u1").await.unwrap().len(), 1);
+        // a store of another kind is not replaced
+        handle
+            .store(Config::builder().with_nonce_store(NoNonceStore).build())
+            .unwrap();
+        let nonce_store = handle.load().nonce_store.clone().unwrap();
+        assert!(nonce_store.check_and_insert("st", 1, ttl).await.unwrap());
+    }
+
+    #[derive(Debug)]
+    struct NoNonceStore;
+
+    #[async_trait::async_trait]
+    impl NonceStore for NoNonceStore {
+        async fn check_and_insert(&self, _: &str, _: u64, _: Duration) -> Result<bool, Error> {
+            Ok(true)
+        }
+    }
+}
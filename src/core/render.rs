@@ -0,0 +1,153 @@
+use std::fmt::Debug;
+
+use crate::core::{
+    config::Config,
+    login::{WxLoginErr, AUTH_FAIL_MSG, LOGIN_FAIL_MSG},
+};
+
+/// The request context for rendering an error response.
+#[derive(Debug, Clone)]
+pub struct ErrorContext<'a> {
+    /// The Accept-Language header of the request.
+    pub accept_language: Option<&'a str>,
+    /// Whether the production mode is on, in which case the detail of errors has been removed.
+    pub production: bool,
+}
+
+/// The rendered error response.
+#[derive(Debug, Clone)]
+pub struct RenderedError {
+    pub status: u16,
+    pub body: serde_json::Value,
+}
+
+/// A renderer of error responses, which is used for login failures and extractor rejections.
+///
+/// The default implementation is [DefaultErrorRenderer], one can implement this trait
+/// to rewrite the status, body shape and message by error code and language.
+pub trait ErrorRenderer: Send + Sync + Debug {
+    /// Render the error to a response.
+    fn render(&self, err: &WxLoginErr, ctx: &ErrorContext<'_>) -> RenderedError;
+}
+
+/// The default [ErrorRenderer], which renders [WxLoginErr] as JSON.
+///
+/// The default messages (in Chinese) can be localized by [with_language](Self::with_language).
+#[derive(Debug, Clone, Default)]
+pub struct DefaultErrorRenderer {
+    languages: Vec<LanguageMessages>,
+}
+
+#[derive(Debug, Clone)]
+struct LanguageMessages {
+    lang: String,
+    login_fail: String,
+    auth_fail: String,
+}
+
+impl DefaultErrorRenderer {
+    /// Create a DefaultErrorRenderer without localized messages.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add the localized messages of login failure and authentication failure,
+    /// which are used if the language (e.g. "en") matches the Accept-Language of the request.
+    pub fn with_language(mut self, lang: &str, login_fail: &str, auth_fail: &str) -> Self {
+        self.languages.push(LanguageMessages {
+            lang: lang.to_ascii_lowercase(),
+            login_fail: login_fail.into(),
+            auth_fail: auth_fail.into(),
+        });
+        self
+    }
+
+    /// Find the localized messages by the preferred languages of Accept-Language (e.g. "en-US,en;q=0.9").
+    fn find_language(&self, accept_language: &str) -> Option<&LanguageMessages> {
+        accept_language
+            .split(',')
+            .filter_map(|v| v.split(';').next())
+            .map(|v| v.trim().to_ascii_lowercase())
+            .find_map(|tag| {
+                self.languages
+                    .iter()
+                    .find(|msgs| tag == msgs.lang || tag.starts_with(&format!("{}-", msgs.lang)))
+            })
+    }
+}
+
+impl ErrorRenderer for DefaultErrorRenderer {
+    fn render(&self, err: &WxLoginErr, ctx: &ErrorContext<'_>) -> RenderedError {
+        let mut err = err.clone();
+        if let Some(msgs) = ctx.accept_language.and_then(|v| self.find_language(v)) {
+            if err.message == LOGIN_FAIL_MSG {
+                err.message = msgs.login_fail.clone();
+            } else if err.message == AUTH_FAIL_MSG {
+                err.message = msgs.auth_fail.clone();
+            }
+        }
+        let mut body = serde_json::to_value(&err).unwrap_or_default();
+        if ctx.production {
+            if let Some(obj) = body.as_object_mut() {
+                obj.remove("detail");
+            }
+        }
+        RenderedError {
+            status: err.status,
+            body,
+        }
+    }
+}
+
+/// Render the error by the renderer of config, or the default renderer if there is no config.
+pub(crate) fn render_error(
+    cfg: Option<&Config>,
+    mut err: WxLoginErr,
+    accept_language: Option<&str>,
+) -> RenderedError {
+    let production = cfg.is_some_and(|cfg| cfg.production);
+    if production {
+        err.detail.clear();
+    }
+    let ctx = ErrorContext {
+        accept_language,
+        production,
+    };
+    match cfg {
+        Some(cfg) => cfg.error_renderer.render(&err, &ctx),
+        None => DefaultErrorRenderer::new().render(&err, &ctx),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_renderer() {
+        let err = WxLoginErr {
+            status: 401,
+            code: "auth-login-session-fail".into(),
+            message: AUTH_FAIL_MSG.into(),
+            detail: "bad stoken".into(),
+            server_time: None,
+        };
+        let renderer =
+            DefaultErrorRenderer::new().with_language("en", "Login failed", "Auth failed");
+        let ctx = ErrorContext {
+            accept_language: Some("en-US,en;q=0.9"),
+            production: false,
+        };
+        let rendered = renderer.render(&err, &ctx);
+        assert_eq!(rendered.status, 401);
+        assert_eq!(rendered.body["message"], "Auth failed");
+        assert_eq!(rendered.body["detail"], "bad stoken");
+        let ctx = ErrorContext {
+            accept_language: Some("zh-CN"),
+            production: true,
+        };
+        let rendered = renderer.render(&err, &ctx);
+        assert_eq!(rendered.body["message"], AUTH_FAIL_MSG);
+        assert!(rendered.body.get("detail").is_none());
+    }
+}
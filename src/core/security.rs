@@ -1,8 +1,17 @@
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm,
+};
+use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tiny_crypto::{
-    encoding::{Encoder, BASE64},
+    encoding::{Encoder, BASE64, HEX},
     sha1, sha1_hex,
     sym::{Aes128, Cipher},
 };
@@ -10,50 +19,235 @@ use tiny_crypto::{
 use crate::core::config::AppInfo;
 
 const SESSION_TOKEN_TAG: u32 = 0x68686868;
+const ST2_VERSION: u8 = 2;
+const AEAD_NONCE_LEN: usize = 12;
 
-#[derive(Debug, Clone)]
-pub struct Error {
-    err: String,
+/// The error of login, authentication and config.
+///
+/// Each variant has a stable short [code](Self::code), which is the code of the error response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The WX-LOGIN-STOKEN header is missing or not a valid string.
+    StokenMissing,
+    /// The stoken is malformed or can not be decrypted.
+    StokenInvalid(String),
+    /// The stoken is in a legacy format which is no longer accepted.
+    StokenFormatExpired,
+    /// The appid is unknown.
+    AppNotFound(String),
+    /// The app info provider fails.
+    AppProviderFail(String),
+    /// The session is older than the max age.
+    SessionExpired,
+    /// The session is logged out, revoked or not recorded by the session store.
+    SessionRevoked,
+    /// The session store fails.
+    SessionStoreFail(String),
+    /// The WX-LOGIN-SIG header is missing or not a valid string.
+    SigMissing,
+    /// The signature is malformed or its scheme is not allowed.
+    SigInvalid(String),
+    /// The signature does not match the request.
+    SigMismatch,
+    /// The signature ts is older than the valid period.
+    SigExpired,
+    /// The signature ts is ahead of the server time beyond the skew, with the ahead milliseconds.
+    SigTsInFuture(u128),
+    /// The signature nonce has been used with the same stoken.
+    NonceReplayed,
+    /// The nonce store fails.
+    NonceStoreFail(String),
+    /// The request signature is required but not verified.
+    SigRequired,
+    /// The data can not be decrypted.
+    DecryptFail(String),
+    /// The config is invalid.
+    Config(String),
 }
-impl From<&str> for Error {
-    fn from(err_str: &str) -> Self {
-        Self {
-            err: err_str.into(),
+impl Error {
+    /// Get the short error code (e.g. "session-expired").
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::StokenMissing => "stoken-missing",
+            Self::StokenInvalid(_) => "stoken-invalid",
+            Self::StokenFormatExpired => "stoken-format-expired",
+            Self::AppNotFound(_) => "appid-not-found",
+            Self::AppProviderFail(_) => "app-info-provider-fail",
+            Self::SessionExpired => "session-expired",
+            Self::SessionRevoked => "session-revoked",
+            Self::SessionStoreFail(_) => "session-store-fail",
+            Self::SigMissing => "sig-missing",
+            Self::SigInvalid(_) => "sig-invalid",
+            Self::SigMismatch => "sig-mismatch",
+            Self::SigExpired => "sig-expired",
+            Self::SigTsInFuture(_) => "sig-ts-in-future",
+            Self::NonceReplayed => "nonce-replayed",
+            Self::NonceStoreFail(_) => "nonce-store-fail",
+            Self::SigRequired => "sig-required",
+            Self::DecryptFail(_) => "decrypt-data-fail",
+            Self::Config(_) => "config-invalid",
         }
     }
 }
-impl From<String> for Error {
-    fn from(err: String) -> Self {
-        Self { err }
-    }
-}
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.err)
+        match self {
+            Self::StokenMissing => f.write_str("no valid WX-LOGIN-STOKEN header"),
+            Self::StokenInvalid(e) => write!(f, "invalid stoken: {e}"),
+            Self::StokenFormatExpired => f.write_str("stoken format is no longer accepted"),
+            Self::AppNotFound(appid) => write!(f, "appid not found: {appid}"),
+            Self::AppProviderFail(e) => write!(f, "app info provider fail: {e}"),
+            Self::SessionExpired => f.write_str("session is expired"),
+            Self::SessionRevoked => f.write_str("session is revoked"),
+            Self::SessionStoreFail(e) => write!(f, "session store fail: {e}"),
+            Self::SigMissing => f.write_str("no valid WX-LOGIN-SIG header"),
+            Self::SigInvalid(e) => write!(f, "invalid sig: {e}"),
+            Self::SigMismatch => f.write_str("sig does not match"),
+            Self::SigExpired => f.write_str("sig ts is expired"),
+            Self::SigTsInFuture(ms) => write!(f, "sig ts is {ms}ms ahead of server"),
+            Self::NonceReplayed => f.write_str("sig nonce is replayed"),
+            Self::NonceStoreFail(e) => write!(f, "nonce store fail: {e}"),
+            Self::SigRequired => f.write_str("request signature is required"),
+            Self::DecryptFail(e) => write!(f, "decrypt data fail: {e}"),
+            Self::Config(e) => write!(f, "invalid config: {e}"),
+        }
     }
 }
 impl std::error::Error for Error {}
 
-#[derive(Debug, Default)]
+/// The versioned formats of session token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenFormat {
+    /// AES-128-CBC with a deterministic IV (legacy).
+    ST1,
+    /// AES-256-GCM with a random nonce, binding (appid, openid) as associated data.
+    ST2,
+}
+impl TokenFormat {
+    /// Get the format from the tag of a session token.
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "ST1" => Some(Self::ST1),
+            "ST2" => Some(Self::ST2),
+            _ => None,
+        }
+    }
+    /// Get the tag of the format.
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Self::ST1 => "ST1",
+            Self::ST2 => "ST2",
+        }
+    }
+}
+
+/// The versioned schemes of request signature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum SigScheme {
+    /// SG1:ts:nonce:sha1(uri:ts:nonce:skey)
+    SG1,
+    /// SG2:ts:nonce:hmac_sha256(skey, method:uri:ts:nonce:sha256(body))
+    SG2,
+}
+impl SigScheme {
+    /// Get the scheme from the tag of a signature.
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "SG1" => Some(Self::SG1),
+            "SG2" => Some(Self::SG2),
+            _ => None,
+        }
+    }
+}
+
+/// The request info covered by a signature.
+///
+/// The method and body are only covered by [SigScheme::SG2].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SigRequest<'a> {
+    pub method: &'a str,
+    pub uri: &'a str,
+    pub body: &'a [u8],
+}
+impl<'a> SigRequest<'a> {
+    /// Create a SigRequest of (method, uri, body).
+    pub fn new(method: &'a str, uri: &'a str, body: &'a [u8]) -> Self {
+        Self { method, uri, body }
+    }
+}
+
+/// A ring of server-side keys (by key id) used to encrypt ST2 session tokens.
+///
+/// New tokens are encrypted by the active key, and tokens encrypted by any key in the ring
+/// are accepted. If the ring is empty, the key is derived from the app secret.
+#[derive(Clone, Default)]
+pub(crate) struct TokenKeyRing {
+    keys: HashMap<String, Vec<u8>>,
+    active: Option<String>,
+    /// Whether the key derived from the app secret (with the empty key id) is kept in a non-empty ring.
+    app_secret_key: bool,
+}
+impl TokenKeyRing {
+    /// Keep accepting the tokens encrypted by the key derived from the app secret.
+    pub(crate) fn set_app_secret_key(&mut self, on: bool) {
+        self.app_secret_key = on;
+    }
+    /// Add a key, the first added key becomes the active one.
+    pub(crate) fn add_key(&mut self, id: &str, key: &[u8]) {
+        self.keys.insert(id.into(), key.into());
+        self.active.get_or_insert_with(|| id.into());
+    }
+    /// Remove all keys, e.g. to replace them with the keys from another source.
+    pub(crate) fn clear_keys(&mut self) {
+        self.keys.clear();
+        self.active = None;
+    }
+    /// Set the active key used to encrypt new tokens.
+    pub(crate) fn set_active(&mut self, id: &str) -> Result<(), Error> {
+        if !self.keys.contains_key(id) {
+            return Err(Error::Config(format!("token key not found: {id}")));
+        }
+        self.active = Some(id.into());
+        Ok(())
+    }
+}
+impl std::fmt::Debug for TokenKeyRing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenKeyRing")
+            .field("keys", &self.keys.keys().collect::<Vec<_>>())
+            .field("active", &self.active)
+            .field("app_secret_key", &self.app_secret_key)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
 pub struct ClientSession {
+    pub session_id: String,
     pub sess_key: String,
     pub sess_token: String,
+    pub sess_time: SystemTime,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct ServerSession {
+    /// The id of the session embedded in the stoken, which is unique among the sessions of an openid.
+    pub session_id: String,
     pub session_key: [u8; 16],
     pub client_sess_key: [u8; 16],
     pub client_sess_time: SystemTime,
+    pub unionid: Option<String>,
 }
 
 pub struct Authority<'a> {
     app_info: &'a AppInfo,
+    key_ring: &'a TokenKeyRing,
 }
 
 impl<'a> Authority<'a> {
-    pub fn new(app_info: &'a AppInfo) -> Self {
-        Self { app_info }
+    pub fn new(app_info: &'a AppInfo, key_ring: &'a TokenKeyRing) -> Self {
+        Self { app_info, key_ring }
     }
 
     fn make_token_key(&self, openid: &str) -> [u8; 16] {
@@ -84,7 +278,7 @@ impl<'a> Authority<'a> {
         iv: &[u8; 16],
         st: &SessionToken,
     ) -> String {
-        let token_bin = bincode::serialize(st).unwrap();
+        let token_bin = bincode::serialize(&LegacySessionToken::from(st)).unwrap();
         let token_enc = Aes128::from_key_array(key).encrypt_with_iv(iv, &token_bin);
         BASE64.to_text(&token_enc)
     }
@@ -97,66 +291,218 @@ impl<'a> Authority<'a> {
     ) -> Result<SessionToken, Error> {
         let token_enc = BASE64
             .from_text(token_str)
-            .map_err(|e| Error::from(e.to_string()))?;
+            .map_err(|e| Error::StokenInvalid(e.to_string()))?;
         let token_bin = Aes128::from_key_array(key).decrypt_with_iv(iv, &token_enc);
-        let sess_token: SessionToken =
-            bincode::deserialize(&token_bin).map_err(|e| Error::from(e.to_string()))?;
+        let sess_token: LegacySessionToken =
+            bincode::deserialize(&token_bin).map_err(|e| Error::StokenInvalid(e.to_string()))?;
         if sess_token.tag != SESSION_TOKEN_TAG {
-            return Err(Error::from(format!("bad token tag: {:#x}", sess_token.tag)));
+            return Err(Error::StokenInvalid(format!(
+                "bad token tag: {:#x}",
+                sess_token.tag
+            )));
         }
-        Ok(sess_token)
+        Ok(sess_token.into())
     }
 
-    pub fn make_client_session(&self, openid: &str, session_key: &[u8; 16]) -> ClientSession {
-        let token_key = self.make_token_key(openid);
-        let token_iv = self.make_token_iv(openid);
-        let sess_token = SessionToken::new(session_key);
+    /// Get the AEAD key of the key id, the empty key id means the key derived from app secret,
+    /// which is retired once a key is added to the ring unless it is explicitly kept.
+    /// The token key derived from the app secret is retired once a key is added to the ring,
+    /// unless it is kept by [TokenKeyRing::set_app_secret_key].
+    fn check_app_secret_key(&self) -> Result<(), Error> {
+        match self.key_ring.keys.is_empty() || self.key_ring.app_secret_key {
+            true => Ok(()),
+            false => Err(Error::StokenInvalid(
+                "app secret token key is retired".into(),
+            )),
+        }
+    }
+
+    fn make_aead_token_key(&self, key_id: &str) -> Result<[u8; 32], Error> {
+        let base_key = match key_id {
+            "" => {
+                self.check_app_secret_key()?;
+                self.app_info.secret.0.as_bytes()
+            }
+            _ => self
+                .key_ring
+                .keys
+                .get(key_id)
+                .ok_or(Error::StokenInvalid(format!(
+                    "token key not found: {key_id}"
+                )))?,
+        };
+        Ok(Sha256::new()
+            .chain_update(base_key)
+            .chain_update(b"ST2")
+            .chain_update(self.app_info.appid.as_bytes())
+            .finalize()
+            .into())
+    }
+
+    fn make_aead_token_aad(&self, openid: &str) -> Vec<u8> {
+        [self.app_info.appid.as_bytes(), b":", openid.as_bytes()].concat()
+    }
+
+    fn make_client_sess_token_str_st2(&self, openid: &str, st: &SessionToken) -> String {
+        let token_bin = bincode::serialize(st).unwrap();
+        let key_id = self.key_ring.active.as_deref().unwrap_or_default();
+        let key = self.make_aead_token_key(key_id).unwrap();
+        let cipher = Aes256Gcm::new(&key.into());
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = self.make_aead_token_aad(openid);
+        let token_enc = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &token_bin,
+                    aad: &aad,
+                },
+            )
+            .unwrap();
+        let header = [&[ST2_VERSION, key_id.len() as u8], key_id.as_bytes()].concat();
+        BASE64.to_text(&[&header, nonce.as_slice(), &token_enc].concat())
+    }
+
+    fn auth_client_sess_token_str_st2(
+        &self,
+        openid: &str,
+        token_str: &str,
+    ) -> Result<SessionToken, Error> {
+        let invalid = |e: &str| Error::StokenInvalid(e.into());
+        let token_enc = BASE64
+            .from_text(token_str)
+            .map_err(|e| invalid(&e.to_string()))?;
+        let (version, token_enc) = token_enc.split_first().ok_or(invalid("empty token"))?;
+        if *version != ST2_VERSION {
+            return Err(invalid(&format!("bad token version: {version}")));
+        }
+        let (key_id_len, token_enc) = token_enc
+            .split_first()
+            .ok_or(invalid("token is too short"))?;
+        if token_enc.len() < *key_id_len as usize + AEAD_NONCE_LEN {
+            return Err(invalid("token is too short"));
+        }
+        let (key_id, token_enc) = token_enc.split_at(*key_id_len as usize);
+        let key_id = std::str::from_utf8(key_id).map_err(|e| invalid(&e.to_string()))?;
+        let (nonce, token_enc) = token_enc.split_at(AEAD_NONCE_LEN);
+        let cipher = Aes256Gcm::new(&self.make_aead_token_key(key_id)?.into());
+        let aad = self.make_aead_token_aad(openid);
+        let token_bin = cipher
+            .decrypt(
+                nonce.into(),
+                Payload {
+                    msg: token_enc,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| invalid("token decryption fail"))?;
+        bincode::deserialize(&token_bin).map_err(|e| invalid(&e.to_string()))
+    }
+
+    pub fn make_client_session(
+        &self,
+        format: TokenFormat,
+        openid: &str,
+        unionid: Option<&str>,
+        session_key: &[u8; 16],
+    ) -> ClientSession {
+        let sess_token = SessionToken::new(session_key, unionid);
+        let token_str = match format {
+            TokenFormat::ST1 => {
+                let token_key = self.make_token_key(openid);
+                let token_iv = self.make_token_iv(openid);
+                self.make_client_sess_token_str(&token_key, &token_iv, &sess_token)
+            }
+            TokenFormat::ST2 => self.make_client_sess_token_str_st2(openid, &sess_token),
+        };
         ClientSession {
+            session_id: sess_token.session_id(),
             sess_key: self.make_client_sess_key_str(session_key, sess_token.seed),
-            sess_token: self.make_client_sess_token_str(&token_key, &token_iv, &sess_token),
+            sess_token: token_str,
+            sess_time: UNIX_EPOCH + Duration::from_secs(sess_token.ts as u64),
         }
     }
 
     pub fn auth_client_session(
         &self,
+        format: TokenFormat,
         openid: &str,
         token_str: &str,
     ) -> Result<ServerSession, Error> {
-        let token_key = self.make_token_key(openid);
-        let token_iv = self.make_token_iv(openid);
-        let sess_token = self.auth_client_sess_token_str(token_str, &token_key, &token_iv)?;
+        let sess_token = match format {
+            TokenFormat::ST1 => {
+                // ST1 tokens are always encrypted by the key derived from the app secret
+                self.check_app_secret_key()?;
+                let token_key = self.make_token_key(openid);
+                let token_iv = self.make_token_iv(openid);
+                self.auth_client_sess_token_str(token_str, &token_key, &token_iv)?
+            }
+            TokenFormat::ST2 => self.auth_client_sess_token_str_st2(openid, token_str)?,
+        };
         Ok(ServerSession {
+            session_id: sess_token.session_id(),
             session_key: sess_token.session_key,
             client_sess_key: self.make_client_sess_key(&sess_token.session_key, sess_token.seed),
             client_sess_time: UNIX_EPOCH + Duration::from_secs(sess_token.ts as u64),
+            unionid: sess_token.unionid,
         })
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn auth_client_sig(
         &self,
         skey: &str,
-        url: &str,
+        scheme: SigScheme,
+        req: &SigRequest,
         ts_ms_str: &str,
         nonce_str: &str,
         sig_str: &str,
+        future_skew: Duration,
         validate: impl FnOnce(Duration, u64) -> bool,
-    ) -> Result<(), Error> {
-        let digist = sha1_hex!(
-            (url.to_string() + ":" + ts_ms_str + ":" + nonce_str + ":" + skey).as_bytes()
-        );
-        if digist != sig_str {
-            Err("bad sig value")?;
+    ) -> Result<u64, Error> {
+        let matched = match scheme {
+            SigScheme::SG1 => {
+                sha1_hex!(
+                    (req.uri.to_string() + ":" + ts_ms_str + ":" + nonce_str + ":" + skey)
+                        .as_bytes()
+                ) == sig_str
+            }
+            SigScheme::SG2 => {
+                let sig = HEX
+                    .from_text(sig_str)
+                    .map_err(|e| Error::SigInvalid(e.to_string()))?;
+                let body_hash = HEX.to_text(&Sha256::digest(req.body));
+                let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(skey.as_bytes())
+                    .map_err(|e| Error::SigInvalid(e.to_string()))?;
+                mac.update(
+                    [req.method, req.uri, ts_ms_str, nonce_str, &body_hash]
+                        .join(":")
+                        .as_bytes(),
+                );
+                // compare in constant time
+                mac.verify_slice(&sig).is_ok()
+            }
+        };
+        if !matched {
+            return Err(Error::SigMismatch);
         }
-        let ts_ms = ts_ms_str.parse::<u64>().map_err(|e| e.to_string())?;
+        let ts_ms = ts_ms_str
+            .parse::<u64>()
+            .map_err(|e| Error::SigInvalid(e.to_string()))?;
         let ts = UNIX_EPOCH + Duration::from_millis(ts_ms);
-        let dur = SystemTime::now()
-            .duration_since(ts)
-            .map_err(|e| e.to_string())?;
-        let nonce = nonce_str.parse::<u64>().map_err(|e| e.to_string())?;
+        // a sig from the future within the skew is regarded as just made
+        let dur = match SystemTime::now().duration_since(ts) {
+            Ok(dur) => dur,
+            Err(e) if e.duration() <= future_skew => Duration::ZERO,
+            Err(e) => return Err(Error::SigTsInFuture(e.duration().as_millis())),
+        };
+        let nonce = nonce_str
+            .parse::<u64>()
+            .map_err(|e| Error::SigInvalid(e.to_string()))?;
         if !validate(dur, nonce) {
-            Err("ts or nonce is invalid")?;
+            return Err(Error::SigExpired);
         }
-        Ok(())
+        Ok(nonce)
     }
 }
 
@@ -166,10 +512,11 @@ struct SessionToken {
     ts: u32,
     session_key: [u8; 16],
     tag: u32,
+    unionid: Option<String>,
 }
 
 impl SessionToken {
-    fn new(session_key: &[u8; 16]) -> Self {
+    fn new(session_key: &[u8; 16], unionid: Option<&str>) -> Self {
         Self {
             seed: fastrand::u32(..),
             ts: SystemTime::now()
@@ -178,6 +525,46 @@ impl SessionToken {
                 .as_secs() as u32,
             session_key: *session_key,
             tag: SESSION_TOKEN_TAG,
+            unionid: unionid.map(Into::into),
+        }
+    }
+
+    /// The session id made of the issue time and the random seed.
+    fn session_id(&self) -> String {
+        format!("{:08x}{:08x}", self.ts, self.seed)
+    }
+}
+
+/// The session token of the ST1 format, whose layout must be kept to decode the issued tokens.
+///
+/// Bincode is not self-describing, so the fields added later (e.g. unionid) only go to ST2.
+#[derive(Serialize, Deserialize)]
+struct LegacySessionToken {
+    seed: u32,
+    ts: u32,
+    session_key: [u8; 16],
+    tag: u32,
+}
+
+impl From<&SessionToken> for LegacySessionToken {
+    fn from(st: &SessionToken) -> Self {
+        Self {
+            seed: st.seed,
+            ts: st.ts,
+            session_key: st.session_key,
+            tag: st.tag,
+        }
+    }
+}
+
+impl From<LegacySessionToken> for SessionToken {
+    fn from(st: LegacySessionToken) -> Self {
+        Self {
+            seed: st.seed,
+            ts: st.ts,
+            session_key: st.session_key,
+            tag: st.tag,
+            unionid: None,
         }
     }
 }
@@ -193,16 +580,17 @@ pub fn decrpyt_data(
     iv_base64: &str,
     session_key: &[u8; 16],
 ) -> Result<String, Error> {
+    let fail = |e: String| Error::DecryptFail(e);
     let encrypted = BASE64
         .from_text(encrypted_data_base64)
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| fail(e.to_string()))?;
     let iv: [u8; 16] = BASE64
         .from_text(iv_base64)
-        .map_err(|e| e.to_string())?
+        .map_err(|e| fail(e.to_string()))?
         .try_into()
-        .or(Err("iv is not 16B len"))?;
+        .or(Err(fail("iv is not 16B len".into())))?;
     let decrypted = Aes128::from_key_array(session_key).decrypt_with_iv(&iv, &encrypted);
-    String::from_utf8(decrypted).map_err(|e| e.to_string().into())
+    String::from_utf8(decrypted).map_err(|e| fail(e.to_string()))
 }
 
 pub mod secret_utils {
@@ -239,24 +627,118 @@ mod tests {
         let app_info = AppInfo {
             appid: "some_appid".into(),
             secret: SecretString("some_secret".into()),
+            fallback_secret: None,
         };
         let openid = "some-openid";
-        let auth = Authority::new(&app_info);
+        let key_ring = TokenKeyRing::default();
+        let auth = Authority::new(&app_info, &key_ring);
         let session_key: [u8; 16] = BASE64
             .from_text("HyVFkGl5F5OQWJZZaNzBBg==")
             .unwrap()
             .try_into()
             .unwrap();
-        let client_sess = auth.make_client_session(openid, &session_key);
-        println!("client_sess: {:?}", client_sess);
-        let server_sess = auth
-            .auth_client_session(openid, &client_sess.sess_token)
-            .unwrap();
-        println!("server_sess: {:?}", server_sess);
-        assert_eq!(
-            client_sess.sess_key,
-            BASE64.to_text(&server_sess.client_sess_key)
+        for format in [TokenFormat::ST1, TokenFormat::ST2] {
+            let client_sess =
+                auth.make_client_session(format, openid, Some("some-unionid"), &session_key);
+            println!("client_sess: {:?}", client_sess);
+            let server_sess = auth
+                .auth_client_session(format, openid, &client_sess.sess_token)
+                .unwrap();
+            println!("server_sess: {:?}", server_sess);
+            assert_eq!(
+                client_sess.sess_key,
+                BASE64.to_text(&server_sess.client_sess_key)
+            );
+            // the legacy ST1 layout has no unionid
+            let unionid = (format == TokenFormat::ST2).then_some("some-unionid");
+            assert_eq!(server_sess.unionid.as_deref(), unionid);
+        }
+    }
+    #[test]
+    fn st2_binds_openid() {
+        let app_info = AppInfo {
+            appid: "some_appid".into(),
+            secret: SecretString("some_secret".into()),
+            fallback_secret: None,
+        };
+        let key_ring = TokenKeyRing::default();
+        let auth = Authority::new(&app_info, &key_ring);
+        let client_sess = auth.make_client_session(TokenFormat::ST2, "some-openid", None, &[7; 16]);
+        assert_ne!(
+            client_sess.sess_token,
+            auth.make_client_session(TokenFormat::ST2, "some-openid", None, &[7; 16])
+                .sess_token
         );
+        assert!(auth
+            .auth_client_session(TokenFormat::ST2, "other-openid", &client_sess.sess_token)
+            .is_err());
+    }
+    #[test]
+    fn token_key_rotation() {
+        let app_info = AppInfo {
+            appid: "some_appid".into(),
+            secret: SecretString("some_secret".into()),
+            fallback_secret: None,
+        };
+        let mut key_ring = TokenKeyRing::default();
+        let secret_sess = Authority::new(&app_info, &key_ring).make_client_session(
+            TokenFormat::ST2,
+            "some-openid",
+            None,
+            &[7; 16],
+        );
+        key_ring.add_key("k1", b"some_token_key_1");
+        // the app secret key is retired by the ring unless it is kept explicitly
+        let auth_secret_sess = |key_ring: &TokenKeyRing| {
+            Authority::new(&app_info, key_ring).auth_client_session(
+                TokenFormat::ST2,
+                "some-openid",
+                &secret_sess.sess_token,
+            )
+        };
+        assert!(auth_secret_sess(&key_ring).is_err());
+        key_ring.set_app_secret_key(true);
+        assert!(auth_secret_sess(&key_ring).is_ok());
+        key_ring.set_app_secret_key(false);
+        // so are the ST1 tokens encrypted by the app secret
+        let st1_sess = Authority::new(&app_info, &TokenKeyRing::default()).make_client_session(
+            TokenFormat::ST1,
+            "some-openid",
+            None,
+            &[7; 16],
+        );
+        let auth_st1_sess = |key_ring: &TokenKeyRing| {
+            Authority::new(&app_info, key_ring).auth_client_session(
+                TokenFormat::ST1,
+                "some-openid",
+                &st1_sess.sess_token,
+            )
+        };
+        assert!(auth_st1_sess(&TokenKeyRing::default()).is_ok());
+        assert!(auth_st1_sess(&key_ring).is_err());
+        key_ring.set_app_secret_key(true);
+        assert!(auth_st1_sess(&key_ring).is_ok());
+        key_ring.set_app_secret_key(false);
+        let client_sess = Authority::new(&app_info, &key_ring).make_client_session(
+            TokenFormat::ST2,
+            "some-openid",
+            None,
+            &[7; 16],
+        );
+        key_ring.add_key("k2", b"some_token_key_2");
+        key_ring.set_active("k2").unwrap();
+        let auth = Authority::new(&app_info, &key_ring);
+        let new_sess = auth.make_client_session(TokenFormat::ST2, "some-openid", None, &[7; 16]);
+        for sess in [&client_sess, &new_sess] {
+            assert!(auth
+                .auth_client_session(TokenFormat::ST2, "some-openid", &sess.sess_token)
+                .is_ok());
+        }
+        key_ring.keys.remove("k1");
+        let auth = Authority::new(&app_info, &key_ring);
+        assert!(auth
+            .auth_client_session(TokenFormat::ST2, "some-openid", &client_sess.sess_token)
+            .is_err());
     }
     #[test]
     fn secret_string() {
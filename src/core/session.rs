@@ -0,0 +1,340 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Debug,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+
+use crate::core::security::Error;
+
+/// The client info of a login or refresh request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// A login session issued to a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionRecord {
+    pub appid: String,
+    pub openid: String,
+    /// The session id embedded in the stoken.
+    pub session_id: String,
+    pub issued_at: SystemTime,
+    /// The time when the session is last authenticated.
+    pub last_seen: SystemTime,
+    /// The client which the session is issued to.
+    pub client: ClientInfo,
+    /// The time after which the session can no longer be used (even for refresh),
+    /// None if the session never expires.
+    pub expires_at: Option<SystemTime>,
+}
+
+impl SessionRecord {
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|v| v <= now)
+    }
+}
+
+/// A server-side store of issued sessions, which makes it possible to log out
+/// or revoke a session before its stoken expires.
+///
+/// Once a store is set, only the sessions recorded (and not revoked) by the store are authenticated.
+/// The built-in implementation is the in-memory [MemorySessionStore], one can implement this trait
+/// to persist the sessions or share them among a cluster (e.g. using a database).
+#[async_trait]
+pub trait SessionStore: Send + Sync + Debug {
+    /// Record a newly issued session.
+    async fn insert(&self, record: SessionRecord) -> Result<(), Error>;
+    /// Check whether the session is recorded and not revoked, and update its last seen time if so.
+    async fn touch(
+        &self,
+        appid: &str,
+        openid: &str,
+        session_id: &str,
+        now: SystemTime,
+    ) -> Result<bool, Error>;
+    /// List the active sessions of an openid.
+    async fn list(&self, appid: &str, openid: &str) -> Result<Vec<SessionRecord>, Error>;
+    /// Revoke the session, return false if it is not found.
+    async fn revoke(&self, appid: &str, openid: &str, session_id: &str) -> Result<bool, Error>;
+    /// Revoke all sessions of an openid, return the number of revoked sessions.
+    async fn revoke_all(&self, appid: &str, openid: &str) -> Result<usize, Error>;
+}
+
+const MIN_PRUNE_LEN: usize = 1024;
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 24 * 3600);
+const DEFAULT_CAPACITY: usize = 100_000;
+
+/// An in-memory [SessionStore], whose sessions are lost on restart.
+///
+/// To bound the memory, the sessions not seen within the idle timeout (30 days by default) are dropped
+/// even if they never expire, and the least recently seen session is evicted if the number of sessions
+/// reaches the capacity (100,000 by default). The clients of the dropped sessions have to login again.
+#[derive(Debug)]
+pub struct MemorySessionStore {
+    inner: Mutex<MemorySessions>,
+    idle_timeout: Option<Duration>,
+    capacity: usize,
+}
+
+type SessionKey = ((String, String), String);
+
+#[derive(Debug, Default)]
+struct MemorySessions {
+    map: HashMap<(String, String), HashMap<String, SessionRecord>>,
+    /// The sessions ordered by the last seen time, to evict the least recently seen one.
+    by_last_seen: BTreeSet<(SystemTime, SessionKey)>,
+    prune_len: usize,
+}
+
+impl Default for MemorySessionStore {
+    fn default() -> Self {
+        Self {
+            inner: Default::default(),
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+}
+
+impl MemorySessionStore {
+    /// Create an empty MemorySessionStore.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the idle timeout after which a session not seen is dropped, None to keep it until it expires.
+    pub fn with_idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Set the max number of sessions.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    fn is_stale(&self, record: &SessionRecord, now: SystemTime) -> bool {
+        record.is_expired(now)
+            || self
+                .idle_timeout
+                .is_some_and(|idle_timeout| record.last_seen + idle_timeout <= now)
+    }
+}
+
+impl MemorySessions {
+    fn len(&self) -> usize {
+        self.by_last_seen.len()
+    }
+
+    fn insert(&mut self, record: SessionRecord) {
+        let key = (record.appid.clone(), record.openid.clone());
+        let session_id = record.session_id.clone();
+        self.by_last_seen
+            .insert((record.last_seen, (key.clone(), session_id.clone())));
+        self.map.entry(key).or_default().insert(session_id, record);
+    }
+
+    fn retain(&mut self, mut f: impl FnMut(&SessionRecord) -> bool) {
+        let by_last_seen = &mut self.by_last_seen;
+        self.map.retain(|key, sessions| {
+            sessions.retain(|session_id, v| {
+                let keep = f(v);
+                if !keep {
+                    by_last_seen.remove(&(v.last_seen, (key.clone(), session_id.clone())));
+                }
+                keep
+            });
+            !sessions.is_empty()
+        });
+    }
+
+    /// Evict the least recently seen session.
+    fn evict_one(&mut self) {
+        if let Some((_, (key, session_id))) = self.by_last_seen.pop_first() {
+            self.remove(&key, &session_id);
+        }
+    }
+
+    fn remove(&mut self, key: &(String, String), session_id: &str) -> Option<SessionRecord> {
+        let sessions = self.map.get_mut(key)?;
+        let record = sessions.remove(session_id)?;
+        if sessions.is_empty() {
+            self.map.remove(key);
+        }
+        self.by_last_seen
+            .remove(&(record.last_seen, (key.clone(), session_id.into())));
+        Some(record)
+    }
+
+    fn remove_all(&mut self, key: &(String, String)) -> Option<HashMap<String, SessionRecord>> {
+        let sessions = self.map.remove(key)?;
+        for (session_id, v) in &sessions {
+            self.by_last_seen
+                .remove(&(v.last_seen, (key.clone(), session_id.clone())));
+        }
+        Some(sessions)
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn insert(&self, record: SessionRecord) -> Result<(), Error> {
+        let now = SystemTime::now();
+        let mut inner = self.inner.lock().unwrap();
+        if inner.len() >= inner.prune_len.min(self.capacity) {
+            inner.retain(|v| !self.is_stale(v, now));
+            inner.prune_len = (inner.len() * 2).max(MIN_PRUNE_LEN);
+        }
+        let key = (record.appid.clone(), record.openid.clone());
+        // a record of the same session is replaced
+        inner.remove(&key, &record.session_id);
+        while inner.len() >= self.capacity {
+            inner.evict_one();
+        }
+        inner.insert(record);
+        Ok(())
+    }
+
+    async fn touch(
+        &self,
+        appid: &str,
+        openid: &str,
+        session_id: &str,
+        now: SystemTime,
+    ) -> Result<bool, Error> {
+        let mut inner = self.inner.lock().unwrap();
+        let key = (appid.to_string(), openid.to_string());
+        let last_seen = match inner
+            .map
+            .get_mut(&key)
+            .and_then(|sessions| sessions.get_mut(session_id))
+        {
+            Some(record) if !self.is_stale(record, now) => {
+                std::mem::replace(&mut record.last_seen, now)
+            }
+            _ => return Ok(false),
+        };
+        let session_key = (key, session_id.to_string());
+        inner.by_last_seen.remove(&(last_seen, session_key.clone()));
+        inner.by_last_seen.insert((now, session_key));
+        Ok(true)
+    }
+
+    async fn list(&self, appid: &str, openid: &str) -> Result<Vec<SessionRecord>, Error> {
+        let now = SystemTime::now();
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .map
+            .get(&(appid.into(), openid.into()))
+            .map(|sessions| {
+                sessions
+                    .values()
+                    .filter(|v| !self.is_stale(v, now))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn revoke(&self, appid: &str, openid: &str, session_id: &str) -> Result<bool, Error> {
+        let mut inner = self.inner.lock().unwrap();
+        Ok(inner
+            .remove(&(appid.into(), openid.into()), session_id)
+            .is_some())
+    }
+
+    async fn revoke_all(&self, appid: &str, openid: &str) -> Result<usize, Error> {
+        let now = SystemTime::now();
+        let mut inner = self.inner.lock().unwrap();
+        let Some(sessions) = inner.remove_all(&(appid.into(), openid.into())) else {
+            return Ok(0);
+        };
+        Ok(sessions.values().filter(|v| !self.is_stale(v, now)).count())
+    }
+}
+
+/// Get the time after which a session issued at the time can no longer be used.
+pub(crate) fn session_expires_at(
+    issued_at: SystemTime,
+    session_max_age: Option<Duration>,
+    refresh_max_age: Option<Duration>,
+) -> Option<SystemTime> {
+    session_max_age.map(|max_age| issued_at + refresh_max_age.map_or(max_age, |v| v.max(max_age)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(openid: &str, session_id: &str, expires_at: Option<SystemTime>) -> SessionRecord {
+        SessionRecord {
+            appid: "app".into(),
+            openid: openid.into(),
+            session_id: session_id.into(),
+            issued_at: SystemTime::now(),
+            last_seen: SystemTime::now(),
+            client: Default::default(),
+            expires_at,
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_session_store() {
+        let store = MemorySessionStore::new();
+        store.insert(record("u1", "s1", None)).await.unwrap();
+        store.insert(record("u1", "s2", None)).await.unwrap();
+        let past = SystemTime::now() - Duration::from_secs(1);
+        store.insert(record("u2", "s1", Some(past))).await.unwrap();
+        let now = SystemTime::now() + Duration::from_secs(10);
+        assert!(store.touch("app", "u1", "s1", now).await.unwrap());
+        assert!(!store.touch("app", "u2", "s1", now).await.unwrap());
+        assert!(!store.touch("other", "u1", "s1", now).await.unwrap());
+        assert!(store.list("app", "u2").await.unwrap().is_empty());
+        let mut sessions = store.list("app", "u1").await.unwrap();
+        sessions.sort_by(|a, b| a.session_id.cmp(&b.session_id));
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].last_seen, now);
+        assert!(store.revoke("app", "u1", "s1").await.unwrap());
+        assert!(!store.revoke("app", "u1", "s1").await.unwrap());
+        assert!(!store.touch("app", "u1", "s1", now).await.unwrap());
+        assert!(store.touch("app", "u1", "s2", now).await.unwrap());
+        store.insert(record("u1", "s3", None)).await.unwrap();
+        assert_eq!(store.revoke_all("app", "u1").await.unwrap(), 2);
+        assert!(store.list("app", "u1").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn memory_session_store_bounded() {
+        let store = MemorySessionStore::new()
+            .with_idle_timeout(Some(Duration::from_secs(60)))
+            .with_capacity(2);
+        let mut idle = record("u1", "s1", None);
+        idle.last_seen -= Duration::from_secs(61);
+        store.insert(idle).await.unwrap();
+        assert!(store.list("app", "u1").await.unwrap().is_empty());
+        assert!(!store
+            .touch("app", "u1", "s1", SystemTime::now())
+            .await
+            .unwrap());
+        // the idle session is pruned before evicting the others
+        store.insert(record("u2", "s1", None)).await.unwrap();
+        store.insert(record("u3", "s1", None)).await.unwrap();
+        assert_eq!(store.list("app", "u2").await.unwrap().len(), 1);
+        // the least recently seen session is evicted
+        let later = SystemTime::now() + Duration::from_secs(1);
+        assert!(store.touch("app", "u2", "s1", later).await.unwrap());
+        store.insert(record("u4", "s1", None)).await.unwrap();
+        assert!(store.list("app", "u3").await.unwrap().is_empty());
+        assert_eq!(store.list("app", "u2").await.unwrap().len(), 1);
+        assert_eq!(store.list("app", "u4").await.unwrap().len(), 1);
+        let inner = store.inner.lock().unwrap();
+        assert_eq!(inner.len(), 2);
+        assert_eq!(inner.map.values().map(HashMap::len).sum::<usize>(), 2);
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use rusqlite::{params, Connection, Row};
+
+use crate::core::{
+    security::Error,
+    session::{ClientInfo, SessionRecord, SessionStore},
+};
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS wx_login_sessions (
+    appid TEXT NOT NULL,
+    openid TEXT NOT NULL,
+    session_id TEXT NOT NULL,
+    issued_at INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    client_ip TEXT,
+    user_agent TEXT,
+    expires_at INTEGER,
+    PRIMARY KEY (appid, openid, session_id)
+)";
+
+/// A [SessionStore] persisted in a SQLite database (requires the `sqlite` feature).
+///
+/// The sessions are kept in the table `wx_login_sessions`, which is created if not exists,
+/// and the expired sessions are deleted when a new session is inserted.
+#[derive(Debug, Clone)]
+pub struct SqliteSessionStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteSessionStore {
+    /// Open the database file, which is created if not exists.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::with_connection(Connection::open(path).map_err(store_fail)?)
+    }
+
+    /// Open a database in memory, whose sessions are lost on restart (e.g. for tests).
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::with_connection(Connection::open_in_memory().map_err(store_fail)?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, Error> {
+        conn.execute(CREATE_TABLE, []).map_err(store_fail)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Run the blocking queries out of the async runtime.
+    async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    ) -> Result<T, Error> {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || f(&conn.lock().unwrap()))
+            .await
+            .map_err(|e| Error::SessionStoreFail(e.to_string()))?
+            .map_err(store_fail)
+    }
+}
+
+#[async_trait]
+impl SessionStore for SqliteSessionStore {
+    async fn insert(&self, record: SessionRecord) -> Result<(), Error> {
+        let now = to_millis(SystemTime::now());
+        self.run(move |conn| {
+            conn.execute(
+                "DELETE FROM wx_login_sessions WHERE expires_at <= ?1",
+                params![now],
+            )?;
+            conn.execute(
+                "INSERT OR REPLACE INTO wx_login_sessions
+                 (appid, openid, session_id, issued_at, last_seen, client_ip, user_agent, expires_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    record.appid,
+                    record.openid,
+                    record.session_id,
+                    to_millis(record.issued_at),
+                    to_millis(record.last_seen),
+                    record.client.ip,
+                    record.client.user_agent,
+                    record.expires_at.map(to_millis),
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn touch(
+        &self,
+        appid: &str,
+        openid: &str,
+        session_id: &str,
+        now: SystemTime,
+    ) -> Result<bool, Error> {
+        let key = (
+            appid.to_string(),
+            openid.to_string(),
+            session_id.to_string(),
+        );
+        let now = to_millis(now);
+        self.run(move |conn| {
+            conn.execute(
+                "UPDATE wx_login_sessions SET last_seen = ?4
+                 WHERE appid = ?1 AND openid = ?2 AND session_id = ?3
+                 AND (expires_at IS NULL OR expires_at > ?4)",
+                params![key.0, key.1, key.2, now],
+            )
+            .map(|n| n > 0)
+        })
+        .await
+    }
+
+    async fn list(&self, appid: &str, openid: &str) -> Result<Vec<SessionRecord>, Error> {
+        let key = (appid.to_string(), openid.to_string());
+        let now = to_millis(SystemTime::now());
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT appid, openid, session_id, issued_at, last_seen, client_ip, user_agent, expires_at
+                 FROM wx_login_sessions
+                 WHERE appid = ?1 AND openid = ?2 AND (expires_at IS NULL OR expires_at > ?3)",
+            )?;
+            let records = stmt.query_map(params![key.0, key.1, now], to_record)?;
+            records.collect()
+        })
+        .await
+    }
+
+    async fn revoke(&self, appid: &str, openid: &str, session_id: &str) -> Result<bool, Error> {
+        let key = (
+            appid.to_string(),
+            openid.to_string(),
+            session_id.to_string(),
+        );
+        self.run(move |conn| {
+            conn.execute(
+                "DELETE FROM wx_login_sessions WHERE appid = ?1 AND openid = ?2 AND session_id = ?3",
+                params![key.0, key.1, key.2],
+            )
+            .map(|n| n > 0)
+        })
+        .await
+    }
+
+    async fn revoke_all(&self, appid: &str, openid: &str) -> Result<usize, Error> {
+        let key = (appid.to_string(), openid.to_string());
+        let now = to_millis(SystemTime::now());
+        self.run(move |conn| {
+            let active: i64 = conn.query_row(
+                "SELECT COUNT(*) FROM wx_login_sessions
+                 WHERE appid = ?1 AND openid = ?2 AND (expires_at IS NULL OR expires_at > ?3)",
+                params![key.0, key.1, now],
+                |row| row.get(0),
+            )?;
+            conn.execute(
+                "DELETE FROM wx_login_sessions WHERE appid = ?1 AND openid = ?2",
+                params![key.0, key.1],
+            )?;
+            Ok(active as usize)
+        })
+        .await
+    }
+}
+
+fn store_fail(e: rusqlite::Error) -> Error {
+    Error::SessionStoreFail(e.to_string())
+}
+
+fn to_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+fn from_millis(millis: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64)
+}
+
+fn to_record(row: &Row) -> rusqlite::Result<SessionRecord> {
+    Ok(SessionRecord {
+        appid: row.get(0)?,
+        openid: row.get(1)?,
+        session_id: row.get(2)?,
+        issued_at: from_millis(row.get(3)?),
+        last_seen: from_millis(row.get(4)?),
+        client: ClientInfo {
+            ip: row.get(5)?,
+            user_agent: row.get(6)?,
+        },
+        expires_at: row.get::<_, Option<i64>>(7)?.map(from_millis),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(openid: &str, session_id: &str, expires_at: Option<SystemTime>) -> SessionRecord {
+        SessionRecord {
+            appid: "app".into(),
+            openid: openid.into(),
+            session_id: session_id.into(),
+            issued_at: from_millis(to_millis(SystemTime::now())),
+            last_seen: from_millis(to_millis(SystemTime::now())),
+            client: ClientInfo {
+                ip: Some("10.0.0.1".into()),
+                user_agent: None,
+            },
+            expires_at,
+        }
+    }
+
+    #[tokio::test]
+    async fn sqlite_session_store() {
+        let store = SqliteSessionStore::open_in_memory().unwrap();
+        let s1 = record("u1", "s1", None);
+        store.insert(s1.clone()).await.unwrap();
+        store.insert(record("u1", "s2", None)).await.unwrap();
+        let past = SystemTime::now() - Duration::from_secs(1);
+        store.insert(record("u2", "s1", Some(past))).await.unwrap();
+        let now = from_millis(to_millis(SystemTime::now() + Duration::from_secs(10)));
+        assert!(store.touch("app", "u1", "s1", now).await.unwrap());
+        assert!(!store.touch("app", "u2", "s1", now).await.unwrap());
+        assert!(!store.touch("other", "u1", "s1", now).await.unwrap());
+        assert!(store.list("app", "u2").await.unwrap().is_empty());
+        let mut sessions = store.list("app", "u1").await.unwrap();
+        sessions.sort_by(|a, b| a.session_id.cmp(&b.session_id));
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(
+            sessions[0],
+            SessionRecord {
+                last_seen: now,
+                ..s1
+            }
+        );
+        assert!(store.revoke("app", "u1", "s1").await.unwrap());
+        assert!(!store.revoke("app", "u1", "s1").await.unwrap());
+        assert!(!store.touch("app", "u1", "s1", now).await.unwrap());
+        assert!(store.touch("app", "u1", "s2", now).await.unwrap());
+        store.insert(record("u1", "s3", None)).await.unwrap();
+        assert_eq!(store.revoke_all("app", "u1").await.unwrap(), 2);
+        assert!(store.list("app", "u1").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn sqlite_session_store_persisted() {
+        let path = std::env::temp_dir().join(format!("wx_login_{}.db", fastrand::u64(..)));
+        let store = SqliteSessionStore::open(&path).unwrap();
+        store.insert(record("u1", "s1", None)).await.unwrap();
+        drop(store);
+        let store = SqliteSessionStore::open(&path).unwrap();
+        let now = SystemTime::now();
+        assert!(store.touch("app", "u1", "s1", now).await.unwrap());
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,73 @@
+//! The shared fakes and helpers of the unit tests.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use tiny_crypto::encoding::{Encoder, HEX};
+
+use crate::core::{
+    config::{AppInfo, ConfigBuilder},
+    wx_api::{Code2SessionResponse, WxApiClient, WxApiError},
+};
+
+/// A fake WeChat API client accepting "good-code" of the app secret "some_secret".
+#[derive(Debug)]
+pub(crate) struct FakeApiClient;
+
+#[async_trait]
+impl WxApiClient for FakeApiClient {
+    async fn code2session(
+        &self,
+        _appid: &str,
+        secret: &str,
+        code: &str,
+    ) -> Result<Code2SessionResponse, WxApiError> {
+        if secret != "some_secret" {
+            return Err(WxApiError::WeChat {
+                errcode: 40125,
+                errmsg: "invalid appsecret".into(),
+            });
+        }
+        match code {
+            "good-code" => Ok(Code2SessionResponse::new(
+                "some-openid".into(),
+                "HyVFkGl5F5OQWJZZaNzBBg==".into(),
+            )
+            .with_unionid("some-unionid".into())),
+            "banned-code" => Ok(Code2SessionResponse::new(
+                "banned-openid".into(),
+                "HyVFkGl5F5OQWJZZaNzBBg==".into(),
+            )),
+            "used-code" => Err(WxApiError::WeChat {
+                errcode: 40163,
+                errmsg: "code been used".into(),
+            }),
+            _ => Err(WxApiError::Decode("invalid code".into())),
+        }
+    }
+}
+
+/// A config builder of the app "some_appid" using [FakeApiClient].
+pub(crate) fn fake_config() -> ConfigBuilder {
+    ConfigBuilder::new()
+        .with_app_info(AppInfo::from("some_appid".into(), "some_secret".into()))
+        .with_api_client(FakeApiClient)
+}
+
+/// Make a SG2 signature of the request with a random nonce.
+pub(crate) fn make_sg2_sig(skey: &str, method: &str, uri: &str, body: &[u8]) -> String {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let nonce = fastrand::u64(..);
+    let body_hash = HEX.to_text(&Sha256::digest(body));
+    let mut mac = Hmac::<Sha256>::new_from_slice(skey.as_bytes()).unwrap();
+    mac.update(format!("{method}:{uri}:{ts}:{nonce}:{body_hash}").as_bytes());
+    format!(
+        "SG2:{ts}:{nonce}:{}",
+        HEX.to_text(&mac.finalize().into_bytes())
+    )
+}
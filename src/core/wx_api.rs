@@ -0,0 +1,509 @@
+use std::{
+    fmt::{Debug, Display},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+use crate::core::security::Error;
+
+pub(crate) const WX_API_BASE_URL: &str = "https://api.weixin.qq.com";
+/// The alternate hosts of WeChat server APIs, which can be used by
+/// [with_api_failover_urls](crate::wx_login::ConfigBuilder::with_api_failover_urls).
+pub const WX_API_BACKUP_URLS: &[&str] = &[
+    "https://api2.weixin.qq.com",
+    "https://sh.api.weixin.qq.com",
+    "https://sz.api.weixin.qq.com",
+];
+const JSCODE2SESSION_PATH: &str = "/sns/jscode2session";
+
+/// The error type of [WxApiClient] calls.
+#[derive(Debug, Clone)]
+pub enum WxApiError {
+    /// Failed to connect to the server, so the request is not sent.
+    Connect(String),
+    /// Failed to send the request or receive the response (e.g. timed out).
+    Call(String),
+    /// Failed to decode the response.
+    Decode(String),
+    /// The WeChat server returned an error (errcode, errmsg).
+    WeChat { errcode: i64, errmsg: String },
+    /// The call is rejected without being sent since the circuit breaker is open.
+    Unavailable(String),
+}
+impl WxApiError {
+    /// Whether the call can be retried safely, i.e. the login code is not consumed by WeChat.
+    ///
+    /// Only the connect failures and the system busy error (-1) are retryable, while a timed out
+    /// call may have consumed the code so that a retry would fail with "code been used".
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Connect(_) | Self::WeChat { errcode: -1, .. })
+    }
+    /// Whether the error indicates the WeChat server is unavailable, which trips the circuit breaker.
+    fn is_outage(&self) -> bool {
+        matches!(self, Self::Connect(_) | Self::Call(_)) || self.is_retryable()
+    }
+}
+impl Display for WxApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connect(e) => write!(f, "connect fail: {e}"),
+            Self::Call(e) => write!(f, "call fail: {e}"),
+            Self::Decode(e) => write!(f, "decode fail: {e}"),
+            Self::WeChat { errcode, errmsg } => write!(f, "wechat error {errcode}: {errmsg}"),
+            Self::Unavailable(e) => write!(f, "wechat unavailable: {e}"),
+        }
+    }
+}
+impl std::error::Error for WxApiError {}
+
+/// The success result of the WeChat code2session API.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Code2SessionResponse {
+    pub session_key: String,
+    pub openid: String,
+    pub unionid: Option<String>,
+}
+impl Code2SessionResponse {
+    /// Create a new Code2SessionResponse from (openid, session_key).
+    pub fn new(openid: String, session_key: String) -> Self {
+        Self {
+            session_key,
+            openid,
+            unionid: None,
+        }
+    }
+    /// Set the unionid of the user.
+    pub fn with_unionid(mut self, unionid: String) -> Self {
+        self.unionid = Some(unionid);
+        self
+    }
+}
+
+/// The raw result of the WeChat code2session API, which is either the session info
+/// or an (errcode, errmsg) pair.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Code2SessionResult {
+    Ok(Code2SessionResponse),
+    Err { errcode: i64, errmsg: String },
+}
+impl From<Code2SessionResult> for Result<Code2SessionResponse, WxApiError> {
+    fn from(res: Code2SessionResult) -> Self {
+        match res {
+            Code2SessionResult::Ok(res) => Ok(res),
+            Code2SessionResult::Err { errcode, errmsg } => {
+                Err(WxApiError::WeChat { errcode, errmsg })
+            }
+        }
+    }
+}
+
+/// The client of WeChat server APIs used by [WxLogin](crate::wx_login::WxLogin).
+///
+/// The default implementation is [ReqwestWxApiClient], one can implement this trait
+/// to use a local stand-in server or an in-memory fake (e.g. in tests).
+#[async_trait]
+pub trait WxApiClient: Send + Sync + Debug {
+    /// Exchange the login code from wx.login for the session info of a user.
+    async fn code2session(
+        &self,
+        appid: &str,
+        secret: &str,
+        code: &str,
+    ) -> Result<Code2SessionResponse, WxApiError>;
+}
+
+/// The options of the HTTP client of [ReqwestWxApiClient].
+#[derive(Debug, Clone)]
+pub(crate) struct HttpClientOptions {
+    pub(crate) base_url: String,
+    pub(crate) connect_timeout: Duration,
+    pub(crate) timeout: Duration,
+    pub(crate) proxy: Option<reqwest::Proxy>,
+    pub(crate) root_certs: Vec<reqwest::Certificate>,
+    pub(crate) failover_urls: Vec<String>,
+    pub(crate) max_retries: u32,
+    pub(crate) retry_backoff: Duration,
+    pub(crate) breaker_threshold: u32,
+    pub(crate) breaker_open_duration: Duration,
+    pub(crate) max_concurrency: Option<usize>,
+}
+impl Default for HttpClientOptions {
+    fn default() -> Self {
+        Self {
+            base_url: WX_API_BASE_URL.into(),
+            connect_timeout: Duration::from_secs(5),
+            timeout: Duration::from_secs(10),
+            proxy: None,
+            root_certs: Vec::new(),
+            failover_urls: Vec::new(),
+            max_retries: 2,
+            retry_backoff: Duration::from_millis(100),
+            breaker_threshold: 5,
+            breaker_open_duration: Duration::from_secs(30),
+            max_concurrency: None,
+        }
+    }
+}
+impl HttpClientOptions {
+    /// Set the proxy of all requests, e.g. "http://proxy.internal:3128".
+    pub(crate) fn set_proxy(&mut self, url: &str) -> Result<(), Error> {
+        let proxy = reqwest::Proxy::all(url)
+            .map_err(|e| Error::Config(format!("invalid api proxy {url}: {e}")))?;
+        self.proxy = Some(proxy);
+        Ok(())
+    }
+    /// Add trusted root certificates in PEM format, which may contain multiple certificates.
+    pub(crate) fn add_root_certs(&mut self, pem: &[u8]) -> Result<(), Error> {
+        let certs = reqwest::Certificate::from_pem_bundle(pem)
+            .map_err(|e| Error::Config(format!("invalid api root cert: {e}")))?;
+        if certs.is_empty() {
+            return Err(Error::Config("no api root cert is found".into()));
+        }
+        self.root_certs.extend(certs);
+        Ok(())
+    }
+}
+
+/// The default [WxApiClient] implementation based on reqwest.
+///
+/// The underlying HTTP client is shared by all clones, which reuses pooled connections
+/// and TLS sessions. The retryable errors (see [WxApiError::is_retryable]) are retried
+/// with jittered exponential backoff, switching to the next failover host if any.
+/// After consecutive outage errors the circuit breaker opens, and the calls fail fast with
+/// [WxApiError::Unavailable] until the open duration elapses.
+#[derive(Debug, Clone)]
+pub struct ReqwestWxApiClient {
+    base_urls: Vec<String>,
+    client: reqwest::Client,
+    max_retries: u32,
+    retry_backoff: Duration,
+    breaker: Arc<CircuitBreaker>,
+    limiter: Option<(usize, Arc<Semaphore>)>,
+}
+impl Default for ReqwestWxApiClient {
+    fn default() -> Self {
+        Self::with_options(&Default::default())
+    }
+}
+impl ReqwestWxApiClient {
+    /// Create a client calling the official WeChat API server.
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Create a client calling the API server of the specified base url (e.g. "http://127.0.0.1:8000").
+    pub fn with_base_url(base_url: &str) -> Self {
+        Self::with_options(&HttpClientOptions {
+            base_url: base_url.into(),
+            ..Default::default()
+        })
+    }
+    /// Create a client with the options.
+    ///
+    /// Panics if the TLS backend cannot be initialized, the same as `reqwest::Client::new`.
+    pub(crate) fn with_options(options: &HttpClientOptions) -> Self {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(options.connect_timeout)
+            .timeout(options.timeout);
+        if let Some(proxy) = &options.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        for cert in &options.root_certs {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+        Self {
+            base_urls: std::iter::once(&options.base_url)
+                .chain(&options.failover_urls)
+                .map(|v| v.trim_end_matches('/').into())
+                .collect(),
+            client: builder.build().expect("build http client fail"),
+            max_retries: options.max_retries,
+            retry_backoff: options.retry_backoff,
+            breaker: Arc::new(CircuitBreaker::new(
+                options.breaker_threshold,
+                options.breaker_open_duration,
+            )),
+            limiter: options
+                .max_concurrency
+                .map(|n| (n, Arc::new(Semaphore::new(n)))),
+        }
+    }
+
+    /// Share the circuit breaker and the concurrency limiter of the old client
+    /// if their options are not changed, e.g. when the config is reloaded.
+    pub(crate) fn inherit_state(&mut self, old: &Self) {
+        if (self.breaker.threshold, self.breaker.open_duration)
+            == (old.breaker.threshold, old.breaker.open_duration)
+        {
+            self.breaker = old.breaker.clone();
+        }
+        if let (Some((n, limiter)), Some((old_n, old_limiter))) = (&mut self.limiter, &old.limiter)
+        {
+            if n == old_n {
+                *limiter = old_limiter.clone();
+            }
+        }
+    }
+
+    async fn call_code2session(
+        &self,
+        base_url: &str,
+        appid: &str,
+        secret: &str,
+        code: &str,
+    ) -> Result<Code2SessionResponse, WxApiError> {
+        let url = base_url.to_string() + JSCODE2SESSION_PATH;
+        let res = self
+            .client
+            .get(url)
+            .query(&Code2SessionRequest::from(appid, secret, code))
+            .send()
+            .await
+            .map_err(|e| match e.is_connect() {
+                true => WxApiError::Connect(e.to_string()),
+                false => WxApiError::Call(e.to_string()),
+            })?;
+        res.json::<Code2SessionResult>()
+            .await
+            .map_err(|e| WxApiError::Decode(e.to_string()))?
+            .into()
+    }
+}
+
+/// A circuit breaker opened by consecutive outage errors.
+///
+/// After the open duration, the calls are let through again and the next failure reopens it
+/// immediately, while a success closes it.
+#[derive(Debug)]
+struct CircuitBreaker {
+    threshold: u32,
+    open_duration: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// Create a circuit breaker, which is disabled if the threshold is 0.
+    fn new(threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            threshold,
+            open_duration,
+            state: Default::default(),
+        }
+    }
+
+    fn check(&self) -> Result<(), WxApiError> {
+        let state = self.state.lock().unwrap();
+        match state.open_until {
+            Some(until) if until > Instant::now() => Err(WxApiError::Unavailable(format!(
+                "circuit breaker is open after {} failures",
+                state.failures
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn record(&self, outage: bool) {
+        if self.threshold == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if !outage {
+            *state = Default::default();
+            return;
+        }
+        state.failures = state.failures.saturating_add(1);
+        if state.failures >= self.threshold {
+            if state.failures == self.threshold {
+                tracing::warn!(
+                    event = "wx_login.circuit_breaker_open",
+                    failures = state.failures,
+                    "wechat api circuit breaker is open"
+                );
+            }
+            state.open_until = Some(Instant::now() + self.open_duration);
+        }
+    }
+}
+
+#[async_trait]
+impl WxApiClient for ReqwestWxApiClient {
+    async fn code2session(
+        &self,
+        appid: &str,
+        secret: &str,
+        code: &str,
+    ) -> Result<Code2SessionResponse, WxApiError> {
+        self.breaker.check()?;
+        let _permit = match &self.limiter {
+            Some((_, limiter)) => Some(limiter.acquire().await.expect("semaphore is never closed")),
+            None => None,
+        };
+        let mut attempt = 0;
+        loop {
+            let base_url = &self.base_urls[attempt as usize % self.base_urls.len()];
+            let res = self.call_code2session(base_url, appid, secret, code).await;
+            let err = match res {
+                Err(err) => err,
+                ok => {
+                    self.breaker.record(false);
+                    return ok;
+                }
+            };
+            self.breaker.record(err.is_outage());
+            if !err.is_retryable() || attempt >= self.max_retries {
+                return Err(err);
+            }
+            self.breaker.check()?;
+            // full jitter of the exponential backoff
+            let backoff = self.retry_backoff.saturating_mul(1 << attempt.min(16));
+            let delay = backoff.mul_f64(fastrand::f64());
+            tracing::warn!(
+                event = "wx_login.code2session_retry",
+                base_url,
+                attempt,
+                ?delay,
+                "retry code2session after error: {err}"
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Code2SessionRequest<'a> {
+    appid: &'a str,
+    secret: &'a str,
+    js_code: &'a str,
+    grant_type: &'a str,
+}
+
+impl<'a> Code2SessionRequest<'a> {
+    fn from(appid: &'a str, secret: &'a str, code: &'a str) -> Self {
+        Self {
+            appid,
+            secret,
+            js_code: code,
+            grant_type: "authorization_code",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    fn parse(json: &str) -> Result<Code2SessionResponse, WxApiError> {
+        serde_json::from_str::<Code2SessionResult>(json)
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn parse_code2session_result() {
+        let res = parse(r#"{"openid":"some-openid","session_key":"HyVFkGl5F5OQWJZZaNzBBg=="}"#);
+        assert_eq!(res.unwrap().openid, "some-openid");
+        let res = parse(
+            r#"{"openid":"some-openid","session_key":"HyVFkGl5F5OQWJZZaNzBBg==","unionid":"some-unionid"}"#,
+        );
+        assert_eq!(res.unwrap().unionid.as_deref(), Some("some-unionid"));
+        let res = parse(r#"{"errcode":40029,"errmsg":"invalid code"}"#);
+        assert!(matches!(
+            res,
+            Err(WxApiError::WeChat { errcode: 40029, .. })
+        ));
+        let res = parse(r#"{"errcode":-1,"errmsg":"system error"}"#);
+        assert!(matches!(res, Err(WxApiError::WeChat { errcode: -1, .. })));
+    }
+
+    /// Serve the responses in turn, and return the base url and the served count.
+    async fn serve(responses: &'static [&'static str]) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let count = Arc::new(AtomicUsize::new(0));
+        let served = count.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = vec![0; 4096];
+                let _ = stream.read(&mut buf).await;
+                let n = served.fetch_add(1, Ordering::SeqCst);
+                let body = responses[n.min(responses.len() - 1)];
+                let resp = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(resp.as_bytes()).await;
+            }
+        });
+        (base_url, count)
+    }
+
+    fn client(base_url: &str, failover_urls: &[&str]) -> ReqwestWxApiClient {
+        ReqwestWxApiClient::with_options(&HttpClientOptions {
+            base_url: base_url.into(),
+            failover_urls: failover_urls.iter().map(|v| v.to_string()).collect(),
+            retry_backoff: Duration::from_millis(1),
+            breaker_threshold: 0,
+            ..Default::default()
+        })
+    }
+
+    const OK: &str = r#"{"openid":"some-openid","session_key":"HyVFkGl5F5OQWJZZaNzBBg=="}"#;
+    const BUSY: &str = r#"{"errcode":-1,"errmsg":"system error"}"#;
+    const USED: &str = r#"{"errcode":40163,"errmsg":"code been used"}"#;
+    // nothing listens on the discard port
+    const CLOSED_URL: &str = "http://127.0.0.1:9";
+
+    #[tokio::test]
+    async fn retry_and_failover() {
+        let (base_url, count) = serve(&[BUSY, OK]).await;
+        let res = client(&base_url, &[]).code2session("a", "s", "c").await;
+        assert_eq!(res.unwrap().openid, "some-openid");
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        let (base_url, count) = serve(&[USED, OK]).await;
+        let res = client(&base_url, &[]).code2session("a", "s", "c").await;
+        assert!(matches!(
+            res,
+            Err(WxApiError::WeChat { errcode: 40163, .. })
+        ));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        let (base_url, count) = serve(&[OK]).await;
+        let res = client(CLOSED_URL, &[&base_url])
+            .code2session("a", "s", "c")
+            .await;
+        assert_eq!(res.unwrap().openid, "some-openid");
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn circuit_breaker() {
+        let client = ReqwestWxApiClient::with_options(&HttpClientOptions {
+            base_url: CLOSED_URL.into(),
+            max_retries: 0,
+            breaker_threshold: 2,
+            max_concurrency: Some(1),
+            ..Default::default()
+        });
+        for _ in 0..2 {
+            let res = client.code2session("a", "s", "c").await;
+            assert!(matches!(res, Err(WxApiError::Connect(_))));
+        }
+        let res = client.code2session("a", "s", "c").await;
+        assert!(matches!(res, Err(WxApiError::Unavailable(_))));
+    }
+}
@@ -10,14 +10,19 @@
 //! use axum::{routing::get, Router};
 //! use wx_login_middleware::preclude::*;
 //! 
+//! // create the layer of wx_login_middleware
+//! // here we use default config of app-info from environment variables
+//! // (e.g. WX_APP_"TheAppID"="TheAppSecret")
+//! let wx_login = wx_login::axum::layer_with_env_var();
+//! 
 //! let app = Router::new()
 //!     // `GET /auth` goes to `auth` which require login authendication
 //!     .route("/auth", get(auth))
-//!     // add the layer of wx_login_middleware for login and authentication
+//!     // add the layer for authentication
+//!     .layer(wx_login.clone())
+//!     // mount the login and refresh handlers after the layer, which only wraps the routes added before
 //!     // by default the login API is `GET|POST /login`
-//!     // here we use default config of app-info from environment variables
-//!     // (e.g. WX_APP_"TheAppID"="TheAppSecret")
-//!     .layer(wx_login::axum::layer_with_env_var());
+//!     .merge(wx_login.login_router());
 //! 
 //! let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
 //! axum::serve(listener, app).await.unwrap();
@@ -37,13 +42,17 @@
 //! use wx_login_middleware::preclude::*;
 //! 
 //! async fn main() -> std::io::Result<()> {
-//!    HttpServer::new(|| {
+//!    // create the middleware of wx_login_middleware
+//!    // here we use config of app-info from environment variables
+//!    // (e.g. WX_APP_"TheAppID"="TheAppSecret")
+//!    let wx_login = wx_login::actix_web::middleware_with_env_var();
+//!    HttpServer::new(move || {
 //!        App::new()
-//!            // add the middleware for login and authentication
+//!            // add the middleware for authentication
+//!            .wrap(wx_login.clone())
+//!            // mount the login and refresh handlers
 //!            // by default the login API is `GET|POST /login`
-//!            // here we use config of app-info from environment variables
-//!            // (e.g. WX_APP_"TheAppID"="TheAppSecret")
-//!            .wrap(wx_login::actix_web::middleware_with_env_var())
+//!            .service(wx_login.login_services())
 //!            // `GET /auth` require login authendication
 //!            .service(auth)
 //!    }).bind(("127.0.0.1", 8080))?.run().await
@@ -57,11 +66,149 @@
 //! # })
 //! ```
 //! 
+//! ## Configuration
+//! 
+//! Besides the `with_*` methods of `ConfigBuilder`, the config can be loaded from a TOML
+//! (or JSON with the extension `.json`) file and from environment variables,
+//! where the values loaded later override the earlier ones:
+//! 
+//! ```ignore
+//! let cfg = wx_login::Config::builder()
+//!     .with_file("wx_login.toml")?
+//!     .with_env_var()
+//!     .build();
+//! ```
+//! 
+//! The config file looks like:
+//! 
+//! ```toml
+//! login_path = "/login"
+//! refresh_path = "/login/refresh"
+//! auth_sig = true
+//! sig_valid_secs = 600
+//! sig_schemes = ["SG1", "SG2"]
+//! session_max_age_secs = 604800
+//! 
+//! [[apps]]
+//! appid = "TheAppID"
+//! # or `secret = "TheAppSecret"`
+//! secret_file = "/run/secrets/wx_app_secret"
+//! 
+//! [[token_keys]]
+//! id = "k1"
+//! key_file = "/run/secrets/wx_token_key"
+//! ```
+//! 
+//! The environment variables are `WX_APP_<appid>` for app secrets, `WX_LOGIN_TOKEN_KEY_<id>`
+//! for token keys and `WX_LOGIN_<NAME>` for other values (e.g. `WX_LOGIN_LOGIN_PATH`,
+//! `WX_LOGIN_AUTH_SIG`, `WX_LOGIN_SIG_SCHEMES=SG1,SG2`, `WX_LOGIN_SESSION_MAX_AGE_SECS`).
+//! Any of them can be given as `<NAME>_FILE` with the path of a file containing the value,
+//! which is convenient for Docker/Kubernetes secrets.
+//! The variables override the values of the config file, where the path lists and token keys are replaced
+//! as a whole. `with_env_var` logs and skips the invalid variables, while `try_with_env_var` returns the error.
+//!
+//! Once a token key is added, the stokens encrypted by the key derived from the app secret are rejected,
+//! set `app_secret_token_key = true` (or `with_app_secret_token_key(true)`) to keep them valid during the migration.
+//! 
+//! The WeChat API calls share one pooled HTTP client of the config, with a 5 seconds connect timeout and a 10 seconds
+//! total timeout by default. In a network reaching the internet only through an egress proxy, set the proxy and
+//! its root certificate by `with_api_proxy` and `with_api_root_cert` (or `api_proxy` and `api_root_cert_files`
+//! in the config file):
+//! 
+//! ```toml
+//! api_connect_timeout_secs = 3
+//! api_timeout_secs = 5
+//! api_proxy = "http://proxy.internal:3128"
+//! api_root_cert_files = ["/etc/ssl/certs/proxy-ca.pem"]
+//! ```
+//! 
+//! The connect failures and the *system busy* error (-1) are retried (2 times by default) with jittered backoff,
+//! switching to the failover hosts if set by `with_api_failover_urls` (e.g. `wx_login::WX_API_BACKUP_URLS`).
+//! Other errors are not retried, since a timed out call may have consumed the single-use login code.
+//! After consecutive failures a circuit breaker opens (see `with_api_circuit_breaker`), and `with_api_max_concurrency`
+//! limits the concurrent calls to stay under the QPS quota of WeChat.
+//! 
+//! For a large number of mini-programs (e.g. stored in a database), implement `AppInfoProvider`
+//! and set it by `with_app_provider`, which is consulted for appids not in the static app map.
+//! Wrap it with `CachedAppInfoProvider` to cache the found apps and the unknown appids:
+//! 
+//! ```ignore
+//! let cfg = wx_login::Config::builder()
+//!     .with_app_provider(
+//!         wx_login::CachedAppInfoProvider::new(MyDbProvider::new(pool), Duration::from_secs(300))
+//!             .with_negative_ttl(Duration::from_secs(30)),
+//!     )
+//!     .build();
+//! ```
+//! The error responses can be customized by `with_error_renderer` (e.g. to localize the messages by
+//! *Accept-Language* or to change the body shape), and `with_production(true)` removes the *detail*
+//! of errors to avoid leaking internal errors to clients:
+//! 
+//! ```ignore
+//! let cfg = wx_login::Config::builder()
+//!     .with_error_renderer(
+//!         wx_login::DefaultErrorRenderer::new()
+//!             .with_language("en", "Login failed", "Login session authentication failed"),
+//!     )
+//!     .with_production(true)
+//!     .build();
+//! ```
+//! The stokens are stateless by default. To log out users or revoke stolen stokens before they expire, set a
+//! `SessionStore` recording the issued sessions, which is consulted on every authentication. The built-in
+//! `MemorySessionStore` keeps sessions in memory (dropping the sessions idle for 30 days and the least recently seen ones
+//! beyond 100,000 sessions by default), and `SqliteSessionStore` of the `sqlite` feature persists them in a SQLite
+//! file (e.g. `SqliteSessionStore::open("sessions.db")?`). One can implement the trait to use another database:
+//! 
+//! ```ignore
+//! let cfg = wx_login::Config::builder()
+//!     .with_session_store(wx_login::MemorySessionStore::new())
+//!     // revoke the oldest session when a user logs in on the 4th device
+//!     .with_max_sessions_per_openid(3)
+//!     .build();
+//! ```
+//! 
+//! With a session store, `WxLogin` can list the active sessions of a user (with the issue time, last seen time,
+//! client ip and user-agent) and revoke some or all of them, e.g. for a customer-support console:
+//! 
+//! ```ignore
+//! let wx_login = wx_login::WxLogin::new(cfg);
+//! for session in wx_login.list_sessions(&appid, &openid).await? {
+//!     println!("{} {:?} {:?}", session.session_id, session.last_seen, session.client.ip);
+//! }
+//! wx_login.revoke_session(&appid, &openid, &session_id).await?;
+//! wx_login.revoke_all_sessions(&appid, &openid).await?;
+//! ```
+//! 
+//! The client ip is the peer address (on axum it requires `into_make_service_with_connect_info::<SocketAddr>()`).
+//! Behind a reverse proxy overwriting `X-Forwarded-For`/`X-Real-IP` (or `Forwarded` on actix-web), set
+//! `trust_proxy_headers = true` (or `with_trust_proxy_headers(true)`) to take it from the headers instead.
+//! 
+//! To add apps or rotate secrets without restarting the server, use a reloadable `ConfigHandle`,
+//! which is shared by all clones of the layer/middleware. An invalid new config is rejected
+//! and the current one is kept. The state in memory (the seen nonces, the sessions of `MemorySessionStore`
+//! and the circuit breaker of the API client) is carried over to the reloaded config:
+//! 
+//! ```ignore
+//! let handle = wx_login::ConfigHandle::with_loader(|| {
+//!     Ok(wx_login::Config::builder().with_file("wx_login.toml")?.try_with_env_var()?.build())
+//! })?;
+//! // reload when the file is modified, or call `handle.reload()` explicitly (e.g. on SIGHUP)
+//! handle.watch_file("wx_login.toml", std::time::Duration::from_secs(5));
+//! let wx_login = wx_login::axum::WxLoginLayer::with_config_handle(handle);
+//! ```
+//! 
 //! ## Protocol
 //! 
 //! ### Login
 //! 
 //! Use GET or POST /login (which is the default path and can be customized with wx_login::Config).
+//! The login, refresh and logout handlers are mounted explicitly (by `login_router` of the axum layer, or `login_services`
+//! and `login_scope` of the actix-web middleware), so they can be nested under a prefix, rate limited or wrapped
+//! by other layers like any other route.
+//! The actix-web middleware passes the login resources through wherever they are mounted, by their names
+//! (e.g. `wx_login.login`), and the axum layer passes the configured paths through. The axum router nested under a prefix
+//! should be added after the layer, which wraps only the routes added before; if it is wrapped anyway, refresh and logout
+//! reuse the result of the layer, while the auth policy applies to them.
 //! 
 //! **Request (GET)**
 //! 
@@ -82,12 +229,20 @@
 //! ```json
 //! {
 //!   "openid": "<the_login_open_id>",
+//!   "unionid": "<the_login_union_id_if_any>",
 //!   "stoken": "<session_token_for_subsequent_request>",
 //!   "skey": "<session_key_for_making_signature>",
+//!   "expires_at": <unix_timestamp_when_session_expires_if_configured>,
 //! }
 //! ```
+//!
+//! Extra fields returned by the login hook (see wx_login::LoginHook) are merged into the success response.
+//!
+//! Concurrent logins with the same appid and code (e.g. fired twice by a page on cold start) are coalesced into one
+//! WeChat call, and a successful login is cached for 5 seconds (see `with_login_dedup_ttl`, or `login_dedup_ttl_secs`
+//! in the config file), so the duplicate requests get the same response instead of *wx-code-used*.
 //! 
-//! Fail (StatusCode 400|401|500):
+//! Fail (StatusCode 400|401|403|429|500|503):
 //! 
 //! ```json
 //! {
@@ -98,6 +253,11 @@
 //! }
 //! ```
 //! 
+//! Errors returned by WeChat server are mapped to distinct codes: *wx-code-invalid* (40029) and *wx-code-used* (40163)
+//! mean the client should call wx.login again, while *wx-rate-limited* (45011), *wx-user-blocked* (40226)
+//! and *wx-system-busy* (-1) should not be retried immediately. The code *wechat-unavailable* (StatusCode 503) means
+//! the WeChat server is considered down and the login fails fast for a while.
+//! 
 //! ### Authentication
 //! 
 //! After login client can attach header *WX-LOGIN-STOKEN* and *WX-LOGIN-SIG* with subsequent request for authentication.
@@ -105,12 +265,19 @@
 //! - *WX-LOGIN-STOKEN*: the session-token from login response
 //! 
 //! - *WX-LOGIN-SIG*: the signature of request uri (path+params), calculated as SG1:ts:nonce:sha1(uri:ts:nonce:skey)
+//!
+//!   or the signature also covering the http method and request body, calculated as
+//!   SG2:ts:nonce:hmac_sha256(skey, method:uri:ts:nonce:sha256(body)) (hex encoded digests),
+//!   the accepted schemes can be chosen with wx_login::ConfigBuilder::with_sig_schemes
+//!
+//! The *nonce* must be unique among the requests signed with the same stoken, otherwise the request is rejected as replayed.
 //! 
 //! ```shell
 //! curl --header "WX-LOGIN-STOKEN=<stoken>" --header "WX-LOGIN-SIG=<sig>" --url "https://<host>/someapi" 
 //! ```
 //! 
-//! If the api server requires authentication (usually by using WxLoginInfo extractor) and the authentication failed,
+//! If the api server requires authentication (by using WxLoginInfo extractor, or by path patterns set with
+//! wx_login::ConfigBuilder::with_protected_path, e.g. "/api/**") and the authentication failed,
 //! the error response (StatusCode 401|500) will be returned:
 //! 
 //! ```json
@@ -119,8 +286,48 @@
 //!   "code": "<short_error_code>",
 //!   "message": "<error_message_for_user>",
 //!   "detail": "<debug_message_for_developer>",
+//!   "server_time": <server_unix_time_in_ms>,
 //! }
 //! ```
+//!
+//! The *code* tells the failure kind: *stoken-missing*, *stoken-invalid*, *stoken-format-expired*, *appid-not-found*,
+//! *session-expired*, *session-revoked*, *sig-missing*, *sig-invalid*, *sig-mismatch*, *sig-expired*, *sig-ts-in-future*, *nonce-replayed*
+//! and *sig-required*, which is also available to the api server as the typed wx_login::Error from the extractor rejections.
+//! A failing app info provider, session store or nonce store yields StatusCode 500 with the code
+//! *app-info-provider-fail*, *session-store-fail* or *nonce-store-fail*.
+//! 
+//! If the session is older than the configured max age (see wx_login::ConfigBuilder::with_session_max_age),
+//! the code *session-expired* is returned and the client should login again.
+//!
+//! The *server_time* can be used by client to compute its clock offset and re-sign the request. Signatures whose ts is
+//! ahead of the server time are allowed within a skew (60 seconds by default), otherwise the code *sig-ts-in-future* is returned.
+//! 
+//! Handlers serving both anonymous and logged-in users can use the OptionalWxLoginInfo extractor, which yields None
+//! if no *WX-LOGIN-STOKEN* is present but still rejects an invalid one. The SignedWxLoginInfo extractor requires a verified
+//! signature even if signature authentication is disabled by config, and the code *sig-required* is returned otherwise.
+//! 
+//! ### Refresh
+//! 
+//! Use POST /login/refresh (which is the default path and can be customized with wx_login::Config) with the same
+//! *WX-LOGIN-STOKEN* and *WX-LOGIN-SIG* headers as authentication, to get a new stoken and skey without calling wx.login again.
+//! The response is the same as login. By default a session can be refreshed until it expires,
+//! which can be extended with wx_login::ConfigBuilder::with_refresh_max_age.
+//! 
+//! ```shell
+//! curl --request POST --header "WX-LOGIN-STOKEN=<stoken>" --header "WX-LOGIN-SIG=<sig>" --url "https://<host>/login/refresh"
+//! ```
+//! 
+//! ### Logout
+//! 
+//! Use POST /login/logout (which is the default path and can be customized with wx_login::Config) with the same
+//! *WX-LOGIN-STOKEN* and *WX-LOGIN-SIG* headers as authentication, the response is StatusCode 204 without body.
+//! If a session store is configured (see wx_login::ConfigBuilder::with_session_store), the session is revoked and
+//! the subsequent requests with the stoken fail with the code *session-revoked*, otherwise the stoken stays valid until
+//! it expires and the client should just discard it. A refreshed session also revokes the old one in the store.
+//! 
+//! ```shell
+//! curl --request POST --header "WX-LOGIN-STOKEN=<stoken>" --header "WX-LOGIN-SIG=<sig>" --url "https://<host>/login/logout"
+//! ```
 //! 
 //! ### Frontend
 //! 
@@ -8,10 +8,29 @@ pub mod wx_login {
     #[cfg(feature = "axum")]
     pub mod actix_web {
         pub use crate::actix_web::{
-            middleware_with_env_var, WxLoginMiddleware, WxLoginMiddlewareService,
+            middleware_with_env_var, WxLoginInfoError, WxLoginMiddleware, WxLoginMiddlewareService,
         };
     }
     pub use crate::core::config::{AppInfo, Config, ConfigBuilder};
-    pub use crate::core::login::{Error, WxLogin, WxLoginErr, WxLoginInfo, WxLoginOk};
-    pub use crate::core::security::{check_signature, decrpyt_data};
+    pub use crate::core::hook::{LoginContext, LoginExtra, LoginHook};
+    pub use crate::core::login::{
+        Error, OptionalWxLoginInfo, SignedWxLoginInfo, WxLogin, WxLoginErr, WxLoginInfo, WxLoginOk,
+        WxLoginRejection,
+    };
+    pub use crate::core::nonce::{MemoryNonceStore, NonceStore};
+    pub use crate::core::provider::{AppInfoProvider, CachedAppInfoProvider};
+    pub use crate::core::reload::ConfigHandle;
+    pub use crate::core::render::{
+        DefaultErrorRenderer, ErrorContext, ErrorRenderer, RenderedError,
+    };
+    pub use crate::core::security::{
+        check_signature, decrpyt_data, SigRequest, SigScheme, TokenFormat,
+    };
+    pub use crate::core::session::{ClientInfo, MemorySessionStore, SessionRecord, SessionStore};
+    #[cfg(feature = "sqlite")]
+    pub use crate::core::sqlite_session::SqliteSessionStore;
+    pub use crate::core::wx_api::{
+        Code2SessionResponse, ReqwestWxApiClient, WxApiClient, WxApiError, WX_API_BACKUP_URLS,
+    };
+    pub use async_trait::async_trait;
 }